@@ -0,0 +1,56 @@
+#![feature(test)]
+extern crate test;
+
+use test::Bencher;
+use event_handler::{
+    prelude::*,
+    event_handler::{EventHandler, DispatchMode},
+    def_emitter::DefEmitter,
+    listener::DefListener,
+};
+
+macro_rules! bench_tags {
+    ($($variant:ident),*) => {
+        #[derive(Debug, PartialEq, Clone, Copy)]
+        enum BenchTags { $($variant),* }
+        const ALL_TAGS: &[BenchTags] = &[$(BenchTags::$variant),*];
+    };
+}
+
+bench_tags!(
+    A0, A1, A2, A3, A4, A5, A6, A7, A8, A9, A10, A11, A12, A13, A14, A15,
+    A16, A17, A18, A19, A20, A21, A22, A23, A24, A25, A26, A27, A28, A29, A30, A31
+);
+
+const LISTENERS: usize = 4096;
+const EVENTS: usize = 256;
+
+fn setup(mode: DispatchMode) -> (EHRc<BenchTags, usize>, EmRC<usize>) {
+    let eh = EventHandler::<BenchTags, usize>::new_ehrc();
+    eh.borrow_mut().set_dispatch_mode(mode);
+    for i in 0..LISTENERS {
+        let li = DefListener::new_lirc(vec![ALL_TAGS[i % ALL_TAGS.len()]]);
+        eh.borrow_mut().add_listener(li).unwrap();
+    }
+    (eh, DefEmitter::<BenchTags>::new_emrc(None))
+}
+
+fn dispatch(b: &mut Bencher, mode: DispatchMode) {
+    let (eh, em) = setup(mode);
+    b.iter(|| {
+        for i in 0..EVENTS {
+            eh.borrow_mut().emit(em.clone(), ALL_TAGS[i % ALL_TAGS.len()]);
+            eh.borrow_mut().consume_next_event();
+        }
+    });
+}
+
+#[bench]
+fn dispatch_linear(b: &mut Bencher) {
+    dispatch(b, DispatchMode::Linear);
+}
+
+#[bench]
+fn dispatch_indexed(b: &mut Bencher) {
+    dispatch(b, DispatchMode::Indexed);
+}
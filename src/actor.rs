@@ -0,0 +1,283 @@
+use std::collections::VecDeque;
+use crate::{prelude::*, event::Event, event_handler::EventHandler};
+use crate::dead_letter::{self, DeadLetter, DeadLetterReason};
+use crate::listener::TriggerError;
+
+/// How many events each actor handles per scheduling round.
+/// Either way the actor going first changes every round.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Fairness {
+    /// One event per actor
+    #[default]
+    RoundRobin,
+    /// Up to this many events per actor
+    Budget(usize),
+}
+
+type Mailbox<T, I> = (LiRC<T, I>, VecDeque<Event<T, I>>);
+/// Events an actor handles in one round
+type Batch<T, I> = (LiRC<T, I>, Vec<Event<T, I>>);
+
+/// Mailboxes of the listeners of a handler in actor mode
+#[derive(Clone, Debug)]
+pub(crate) struct Actors<T: Tag, I: Id> {
+    fairness: Fairness,
+    mailboxes: Vec<Mailbox<T, I>>,
+    /// Where the next round starts in `mailboxes`
+    cursor: usize,
+}
+
+impl<T: Tag, I: Id> Actors<T, I> {
+    pub(crate) fn new(fairness: Fairness) -> Self {
+        Self { fairness, mailboxes: Vec::new(), cursor: 0 }
+    }
+    fn enqueue<'a>(&mut self, listeners: impl IntoIterator<Item = &'a LiRC<T, I>>, event: &Event<T, I>) -> Option<DeadLetter<T, I>> {
+        let mut enqueued = false;
+        for li in listeners {
+            match self.mailboxes.iter_mut().find(|(l, _)| l == li) {
+                Some((_, mailbox)) => mailbox.push_back(event.clone()),
+                None => self.mailboxes.push((li.clone(), VecDeque::from([event.clone()]))),
+            }
+            enqueued = true;
+        }
+        (!enqueued).then(|| DeadLetter { event: event.clone(), reason: DeadLetterReason::NoListeners, errors: vec![] })
+    }
+    /// Takes the events each actor handles this round, in the order they are handled
+    fn schedule(&mut self) -> Vec<Batch<T, I>> {
+        let budget = match self.fairness {
+            Fairness::RoundRobin => 1,
+            Fairness::Budget(n) => n.max(1),
+        };
+        let start = self.cursor % self.mailboxes.len().max(1);
+        self.cursor = start + 1;
+
+        let (before, after) = self.mailboxes.split_at_mut(start);
+        after.iter_mut().chain(before.iter_mut())
+            .filter(|(_, mailbox)| !mailbox.is_empty())
+            .map(|(li, mailbox)| {
+                let n = budget.min(mailbox.len());
+                (li.clone(), mailbox.drain(..n).collect())
+            })
+            .collect()
+    }
+    fn remove(&mut self, listener: &LiRC<T, I>) {
+        self.mailboxes.retain(|(li, _)| li != listener);
+    }
+    fn get_mailbox_len(&self, listener: &LiRC<T, I>) -> usize {
+        self.mailboxes.iter().find(|(li, _)| li == listener).map_or(0, |(_, mailbox)| mailbox.len())
+    }
+    fn get_pending(&self) -> usize {
+        self.mailboxes.iter().map(|(_, mailbox)| mailbox.len()).sum()
+    }
+}
+
+/// Hands `event` to `listeners` right away, or puts it in their mailboxes in actor mode
+pub(crate) fn deliver<'a, T: Tag, I: Id + 'a>(actors: &mut Option<Actors<T, I>>, listeners: impl IntoIterator<Item = &'a LiRC<T, I>>, event: &Event<T, I>) -> Option<DeadLetter<T, I>> {
+    match actors {
+        Some(actors) => actors.enqueue(listeners, event),
+        None => dead_letter::deliver(listeners, event),
+    }
+}
+
+impl<T: Tag, I: Id> EventHandler<T, I> {
+    pub fn get_actor_mode(&self) -> Option<Fairness> {
+        self.get_actors().as_ref().map(|a| a.fairness)
+    }
+    /// In actor mode, broadcasting puts events in the mailboxes of the triggered
+    /// listeners, which only handle them when `run_actors` is called.
+    /// Leaving actor mode first runs the actors until every mailbox is empty.
+    pub fn set_actor_mode(&mut self, fairness: Option<Fairness>) {
+        match fairness {
+            Some(fairness) => self.get_actors_mut().get_or_insert_with(|| Actors::new(fairness)).fairness = fairness,
+            None => {
+                self.run_actors_until_idle();
+                *self.get_actors_mut() = None;
+            }
+        }
+    }
+    /// Events waiting in the mailbox of `listener`
+    pub fn get_mailbox_len(&self, listener: &LiRC<T, I>) -> usize {
+        self.get_actors().as_ref().map_or(0, |a| a.get_mailbox_len(listener))
+    }
+    /// Events waiting in every mailbox
+    pub fn get_pending_mail(&self) -> usize {
+        self.get_actors().as_ref().map_or(0, |a| a.get_pending())
+    }
+    pub(crate) fn remove_mailbox(&mut self, listener: &LiRC<T, I>) {
+        if let Some(actors) = self.get_actors_mut() {
+            actors.remove(listener);
+        }
+    }
+    /// Runs one scheduling round, returns how many events were handled.
+    /// Events failed or deferred by an actor become dead letters with the actor's error.
+    pub fn run_actors(&mut self) -> usize {
+        let Some(batches) = self.get_actors_mut().as_mut().map(|a| a.schedule()) else { return 0 };
+        let mut handled = 0;
+        for (li, events) in batches {
+            for e in events {
+                #[cfg(test)]
+                println!("{} ran actor {:?} on {:?}", self, li.borrow().get_id(), e);
+
+                // Events the actor emitted are drained while still caused by `e`
+                let result = self.with_dispatching(&e, |eh| {
+                    let result = li.borrow().try_on_triggers(vec![e.clone()]);
+                    eh.drain_inbox();
+                    result
+                });
+                if let Err(error) = result {
+                    let reason = match error {
+                        TriggerError::Failed(_) => DeadLetterReason::AllFailed,
+                        TriggerError::Deferred(_) => DeadLetterReason::Deferred,
+                    };
+                    self.bury(DeadLetter { event: e, reason, errors: vec![error.to_string()] });
+                }
+                handled += 1;
+            }
+        }
+        handled
+    }
+    /// Runs scheduling rounds until every mailbox is empty, returns how many events were handled
+    pub fn run_actors_until_idle(&mut self) -> usize {
+        let mut handled = 0;
+        while self.get_pending_mail() > 0 {
+            handled += self.run_actors();
+        }
+        handled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        def_emitter::DefEmitter as DEm,
+        dead_letter::DeadLetterSink,
+        state_machine::{StateMachine, InvalidPolicy},
+        tests::{TestTags::{self, *}, Recorder},
+    };
+    use super::*;
+
+    type EH = EventHandler<TestTags, usize>;
+
+    /// Handler in actor mode, with `tags` broadcast to a listener taking T1 and T2, and one taking T1
+    fn mailboxes(fairness: Fairness, tags: &[TestTags]) -> (EH, Recorder, Recorder) {
+        let mut eh = EH::new();
+        let em = DEm::<TestTags>::new_emrc(None);
+        let slow = Recorder::new(vec![T1, T2], None);
+        let fast = Recorder::new(vec![T1], None);
+        eh.add_listener(slow.as_lirc()).unwrap();
+        eh.add_listener(fast.as_lirc()).unwrap();
+        eh.set_actor_mode(Some(fairness));
+        for tag in tags {
+            eh.emit(em.clone(), *tag);
+        }
+        while eh.get_stack_len() > 0 {
+            eh.consume_next_event();
+        }
+        (eh, slow, fast)
+    }
+
+    #[test]
+    fn events_wait_in_mailboxes() {
+        let (mut eh, slow, fast) = mailboxes(Fairness::RoundRobin, &[T1, T1, T2]);
+        assert_eq!(slow.received_tags(), vec![]);
+        assert_eq!(eh.get_mailbox_len(&slow.as_lirc()), 3);
+        assert_eq!(eh.get_mailbox_len(&fast.as_lirc()), 2);
+        assert_eq!(eh.get_pending_mail(), 5);
+
+        // Removed listeners lose their mailbox
+        eh.remove_listener(&fast.as_lirc()).unwrap();
+        assert_eq!(eh.get_pending_mail(), 3);
+        assert_eq!(eh.run_actors_until_idle(), 3);
+        assert_eq!(fast.received_tags(), vec![]);
+    }
+
+    #[test]
+    fn fairness() {
+        // LIFO stack: the mailboxes get T2 first
+        let (mut eh, slow, fast) = mailboxes(Fairness::RoundRobin, &[T1, T1, T1, T2]);
+        assert_eq!(eh.run_actors(), 2);
+        assert_eq!(slow.received_tags(), vec![Some(T2)]);
+        assert_eq!(fast.received_tags(), vec![Some(T1)]);
+
+        eh.set_actor_mode(Some(Fairness::Budget(2)));
+        assert_eq!(eh.run_actors(), 4);
+        assert_eq!(slow.received_tags(), vec![Some(T2), Some(T1), Some(T1)]);
+        assert_eq!(fast.received_tags(), vec![Some(T1), Some(T1), Some(T1)]);
+    }
+
+    #[test]
+    fn leaving_actor_mode() {
+        let (mut eh, slow, _) = mailboxes(Fairness::RoundRobin, &[T1, T2]);
+        eh.set_actor_mode(None);
+        assert_eq!(eh.get_actor_mode(), None);
+        assert_eq!(eh.get_pending_mail(), 0);
+        assert_eq!(slow.received_tags().len(), 2);
+
+        // Events are handed over right away again
+        eh.emit(DEm::<TestTags>::new_emrc(None), T2);
+        eh.consume_next_event();
+        assert_eq!(slow.received_tags().len(), 3);
+    }
+
+    #[test]
+    fn failing_actors() {
+        let mut eh = EH::new();
+        let em = DEm::<TestTags>::new_emrc(None);
+        let mut broken = Recorder::new(vec![T2], None);
+        broken.error = Some("broken");
+        let mut busy = Recorder::new(vec![T3], None);
+        busy.error = Some("busy");
+        busy.deferred = true;
+        eh.add_listener(broken.as_lirc()).unwrap();
+        eh.add_listener(busy.as_lirc()).unwrap();
+        eh.set_dead_letter_sink(DeadLetterSink::Collect);
+        eh.set_actor_mode(Some(Fairness::RoundRobin));
+
+        for tag in [T1, T2, T3] {
+            eh.emit(em.clone(), tag);
+        }
+        while eh.get_stack_len() > 0 {
+            eh.consume_next_event();
+        }
+        // T1 reaches nobody, with or without mailboxes
+        assert_eq!(eh.get_dead_letter_count(), 1);
+        // LIFO stack: the mailbox of `busy` gets mail first, so it runs first
+        assert_eq!(eh.run_actors(), 2);
+
+        let reasons: Vec<_> = eh.get_dead_letters().iter().map(|l| (l.event.get_tag(), l.reason, l.errors.clone())).collect();
+        assert_eq!(reasons, vec![
+            (Some(T1), DeadLetterReason::NoListeners, vec![]),
+            (Some(T3), DeadLetterReason::Deferred, vec!["busy".to_string()]),
+            (Some(T2), DeadLetterReason::AllFailed, vec!["broken".to_string()]),
+        ]);
+    }
+
+    #[test]
+    fn actor_emissions_are_caused_by_the_handled_event() {
+        let eh = EH::new_ehrc();
+        let em = DEm::<TestTags>::new_emrc(None);
+        let mut sm = StateMachine::new((), InvalidPolicy::Ignore);
+        sm.add_transition((), T1, (), Some(T2));
+        sm.register(&eh).unwrap();
+        eh.borrow_mut().set_actor_mode(Some(Fairness::RoundRobin));
+
+        eh.borrow_mut().emit(em, T1);
+        let root = eh.borrow().peek_next().unwrap().get_id();
+        eh.borrow_mut().consume_next_event();
+        assert_eq!(eh.borrow().get_stack_len(), 0);
+        assert_eq!(eh.borrow_mut().run_actors(), 1);
+
+        let handler = eh.borrow();
+        let emitted = handler.peek_next().unwrap();
+        assert_eq!(emitted.get_tag(), Some(T2));
+        assert_eq!((emitted.get_cause(), emitted.get_depth()), (Some(root), 1));
+    }
+
+    #[test]
+    fn clones_start_with_empty_mailboxes() {
+        let (eh, _, _) = mailboxes(Fairness::Budget(3), &[T1]);
+        let clone = eh.clone();
+        assert_eq!(clone.get_actor_mode(), Some(Fairness::Budget(3)));
+        assert_eq!(clone.get_pending_mail(), 0);
+    }
+}
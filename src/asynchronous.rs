@@ -0,0 +1,292 @@
+use std::{
+    collections::VecDeque,
+    future::Future,
+    pin::Pin,
+    rc::Weak,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Wake, Waker},
+};
+use futures_core::Stream;
+use crate::{prelude::*, event::Event, event_handler::{EventHandler, ObserverHandle}};
+
+/// Events broadcast since the last poll, and the task to wake for the next one
+struct Waiting<T: Tag, I: Id> {
+    events: VecDeque<Event<T, I>>,
+    waker: Option<Waker>,
+    /// Set once the handler is dropped
+    closed: bool,
+}
+
+impl<T: Tag, I: Id> Waiting<T, I> {
+    fn poll_next(&mut self, cx: &mut Context<'_>) -> Poll<Option<Event<T, I>>> {
+        if let Some(e) = self.events.pop_front() {
+            return Poll::Ready(Some(e));
+        }
+        if self.closed {
+            return Poll::Ready(None);
+        }
+        self.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+/// Owned by the observer, so it closes the subscription when the handler drops the observer
+struct Closer<T: Tag, I: Id>(Weak<RefCell<Waiting<T, I>>>);
+
+impl<T: Tag, I: Id> Drop for Closer<T, I> {
+    fn drop(&mut self) {
+        let Some(state) = self.0.upgrade() else { return };
+        let waker = {
+            let mut state = state.borrow_mut();
+            state.closed = true;
+            state.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+/// Observer of a handler queueing the broadcast events matching a tag matcher
+struct Subscription<T: Tag, I: Id> {
+    state: Rc<RefCell<Waiting<T, I>>>,
+    _observer: ObserverHandle<T, I>,
+}
+
+impl<T: Tag, I: Id> Subscription<T, I> {
+    fn new(eh: &EHRc<T, I>, matcher: impl Fn(&T) -> bool + 'static) -> Self {
+        let state = Rc::new(RefCell::new(Waiting { events: VecDeque::new(), waker: None, closed: false }));
+        let closer = Closer(Rc::downgrade(&state));
+        let observer = EventHandler::observe(eh, move |e| {
+            let Some(state) = closer.0.upgrade() else { return };
+            if !e.get_tag().is_some_and(|t| matcher(&t)) {
+                return;
+            }
+            let waker = {
+                let mut state = state.borrow_mut();
+                state.events.push_back(e.clone());
+                state.waker.take()
+            };
+            // Woken tasks run once the handler is done broadcasting, if the executor is any good
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+        });
+        Self { state, _observer: observer }
+    }
+}
+
+/// Future of the next broadcast event matching a tag matcher,
+/// `None` if the handler is dropped first
+pub struct NextEvent<T: Tag, I: Id> {
+    subscription: Option<Subscription<T, I>>,
+}
+
+impl<T: Tag, I: Id> Future for NextEvent<T, I> {
+    type Output = Option<Event<T, I>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let Some(subscription) = &self.subscription else { return Poll::Ready(None) };
+        let poll = subscription.state.borrow_mut().poll_next(cx);
+        if poll.is_ready() {
+            self.subscription = None;
+        }
+        poll
+    }
+}
+
+/// Stream of every broadcast event matching a tag matcher, ending when the handler is dropped.
+/// Events broadcast between polls are kept until they are taken.
+pub struct EventStream<T: Tag, I: Id> {
+    subscription: Subscription<T, I>,
+}
+
+impl<T: Tag, I: Id> EventStream<T, I> {
+    /// Events broadcast and not taken yet
+    pub fn get_pending(&self) -> usize {
+        self.subscription.state.borrow().events.len()
+    }
+}
+
+impl<T: Tag, I: Id> Stream for EventStream<T, I> {
+    type Item = Event<T, I>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.subscription.state.borrow_mut().poll_next(cx)
+    }
+}
+
+/// Awaiting the events broadcast by a shared handler, with any executor
+pub trait AsyncHandler<T: Tag, I: Id> {
+    /// Resolves with the first event broadcast from now on whose tag matches `matcher`
+    fn next_event(&self, matcher: impl Fn(&T) -> bool + 'static) -> NextEvent<T, I>;
+    /// Yields every event broadcast from now on whose tag matches `matcher`
+    fn events(&self, matcher: impl Fn(&T) -> bool + 'static) -> EventStream<T, I>;
+}
+
+impl<T: Tag, I: Id> AsyncHandler<T, I> for EHRc<T, I> {
+    fn next_event(&self, matcher: impl Fn(&T) -> bool + 'static) -> NextEvent<T, I> {
+        NextEvent { subscription: Some(Subscription::new(self, matcher)) }
+    }
+    fn events(&self, matcher: impl Fn(&T) -> bool + 'static) -> EventStream<T, I> {
+        EventStream { subscription: Subscription::new(self, matcher) }
+    }
+}
+
+type Task = Pin<Box<dyn Future<Output = ()>>>;
+
+struct TaskWaker {
+    task: usize,
+    ready: Arc<Mutex<VecDeque<usize>>>,
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+    fn wake_by_ref(self: &Arc<Self>) {
+        let mut ready = self.ready.lock().unwrap();
+        if !ready.contains(&self.task) {
+            ready.push_back(self.task);
+        }
+    }
+}
+
+/// Single-threaded executor running its tasks only when asked to, between consuming events
+#[derive(Default)]
+pub struct LocalExecutor {
+    tasks: Vec<Option<Task>>,
+    ready: Arc<Mutex<VecDeque<usize>>>,
+}
+
+impl Debug for LocalExecutor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LocalExecutor")
+            .field("pending", &self.get_pending())
+            .field("ready", &self.ready.lock().unwrap())
+            .finish()
+    }
+}
+
+impl LocalExecutor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn spawn(&mut self, task: impl Future<Output = ()> + 'static) {
+        self.tasks.push(Some(Box::pin(task)));
+        self.ready.lock().unwrap().push_back(self.tasks.len() - 1);
+    }
+    /// Tasks not done yet
+    pub fn get_pending(&self) -> usize {
+        self.tasks.iter().filter(|t| t.is_some()).count()
+    }
+    /// Polls woken tasks until none is left to poll, returns how many tasks are still pending
+    pub fn run_until_stalled(&mut self) -> usize {
+        loop {
+            let Some(id) = self.ready.lock().unwrap().pop_front() else { break };
+            let Some(task) = self.tasks[id].as_mut() else { continue };
+            let waker = Waker::from(Arc::new(TaskWaker { task: id, ready: self.ready.clone() }));
+            if task.as_mut().poll(&mut Context::from_waker(&waker)).is_ready() {
+                self.tasks[id] = None;
+            }
+        }
+        self.get_pending()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::poll_fn;
+    use crate::{def_emitter::DefEmitter as DEm, tests::TestTags::{self, *}};
+    use super::*;
+
+    type Received = Rc<RefCell<Vec<Option<TestTags>>>>;
+
+    fn consume_all(eh: &EHRc<TestTags, usize>) {
+        while eh.borrow().get_stack_len() > 0 {
+            eh.borrow_mut().consume_next_event();
+        }
+    }
+
+    /// Spawns a task pushing the tags of the events of `stream`, then `None` once it ends
+    fn spawn_reader(executor: &mut LocalExecutor, mut stream: EventStream<TestTags, usize>, received: &Received) {
+        let received = received.clone();
+        executor.spawn(async move {
+            while let Some(e) = poll_fn(|cx| Pin::new(&mut stream).poll_next(cx)).await {
+                received.borrow_mut().push(e.get_tag());
+            }
+            received.borrow_mut().push(None);
+        });
+    }
+
+    #[test]
+    fn next_event_resolves_with_the_first_match() {
+        let eh: EHRc<TestTags, usize> = EventHandler::new().into();
+        let em = DEm::<TestTags>::new_emrc(None);
+        let mut executor = LocalExecutor::new();
+        let received: Received = Default::default();
+
+        let (next, received2) = (eh.next_event(|t| matches!(t, T4(_))), received.clone());
+        executor.spawn(async move {
+            let tag = next.await.and_then(|e| e.get_tag());
+            received2.borrow_mut().push(tag);
+        });
+        assert_eq!(executor.run_until_stalled(), 1);
+        let observers = eh.borrow().get_observer_count();
+
+        for tag in [T2, T4(3), T4(4)] {
+            eh.borrow_mut().emit(em.clone(), tag);
+        }
+        // Nothing runs until the events are consumed
+        assert_eq!(executor.run_until_stalled(), 1);
+        consume_all(&eh);
+        assert_eq!(executor.run_until_stalled(), 0);
+        // The stack is LIFO, so T4(4) is broadcast first
+        assert_eq!(*received.borrow(), vec![Some(T4(4))]);
+        // The resolved future let go of its observer
+        assert_eq!(eh.borrow().get_observer_count(), observers - 1);
+    }
+
+    #[test]
+    fn streams_keep_events_until_taken() {
+        let eh: EHRc<TestTags, usize> = EventHandler::new().into();
+        let em = DEm::<TestTags>::new_emrc(None);
+        let mut executor = LocalExecutor::new();
+        let received: Received = Default::default();
+
+        let stream = eh.events(|t| *t == T1);
+        for tag in [T1, T2, T1] {
+            eh.borrow_mut().emit(em.clone(), tag);
+        }
+        consume_all(&eh);
+        assert_eq!(stream.get_pending(), 2);
+
+        spawn_reader(&mut executor, stream, &received);
+        assert_eq!(executor.run_until_stalled(), 1);
+        assert_eq!(*received.borrow(), vec![Some(T1), Some(T1)]);
+        eh.borrow_mut().emit(em.clone(), T1);
+        consume_all(&eh);
+        executor.run_until_stalled();
+        assert_eq!(received.borrow().len(), 3);
+    }
+
+    #[test]
+    fn dropping_the_handler_ends_streams_and_futures() {
+        let eh: EHRc<TestTags, usize> = EventHandler::new().into();
+        let mut executor = LocalExecutor::new();
+        let received: Received = Default::default();
+
+        // Streams and futures do not keep the handler alive
+        spawn_reader(&mut executor, eh.events(|_| true), &received);
+        let (next, received2) = (eh.next_event(|_| true), received.clone());
+        executor.spawn(async move {
+            let tag = next.await.and_then(|e| e.get_tag());
+            received2.borrow_mut().push(tag);
+        });
+        assert_eq!(executor.run_until_stalled(), 2);
+
+        drop(eh);
+        assert_eq!(executor.run_until_stalled(), 0);
+        assert_eq!(*received.borrow(), vec![None, None]);
+    }
+}
@@ -0,0 +1,176 @@
+use std::collections::{HashMap, HashSet};
+use crate::{prelude::*, event::Event, event_handler::EventHandler};
+
+/// An event and every logged event emitted in reaction to it
+#[derive(Debug, Clone, PartialEq)]
+pub struct CausalTree<T: Tag, I: Id> {
+    pub event: Event<T, I>,
+    pub reactions: Vec<CausalTree<T, I>>,
+}
+
+impl<T: Tag, I: Id> CausalTree<T, I> {
+    pub fn event_count(&self) -> usize {
+        1 + self.reactions.iter().map(|r| r.event_count()).sum::<usize>()
+    }
+    pub fn depth(&self) -> usize {
+        1 + self.reactions.iter().map(|r| r.depth()).max().unwrap_or(0)
+    }
+}
+
+/// Walking causes only sees events still held by the causal log,
+/// see `EventHandler::set_causal_log_capacity`
+impl<T: Tag, I: Id> EventHandler<T, I> {
+    pub fn get_logged_event(&self, event_id: usize) -> Option<&Event<T, I>> {
+        self.get_causal_log().iter().rev().find(|e| e.get_id() == event_id)
+    }
+    /// The logged event with id `event_id` preceded by its causes, root first
+    pub fn get_causal_chain(&self, event_id: usize) -> Vec<Event<T, I>> {
+        let mut chain = vec![];
+        let mut next = self.get_logged_event(event_id);
+        while let Some(e) = next {
+            // Guards against cause ids looping back, which can only be set by hand
+            if chain.iter().any(|c: &Event<T, I>| c.get_id() == e.get_id()) {
+                break;
+            }
+            chain.push(e.clone());
+            next = e.get_cause().and_then(|cause| self.get_logged_event(cause));
+        }
+        chain.reverse();
+        chain
+    }
+    /// The oldest logged cause of the event with id `event_id`
+    pub fn get_causal_root(&self, event_id: usize) -> Option<Event<T, I>> {
+        self.get_causal_chain(event_id).into_iter().next()
+    }
+    /// The logged event with id `event_id` and all of its logged reactions.
+    /// Events are only visited once, so hand-set causes looping back cannot recurse forever.
+    pub fn get_causal_tree(&self, event_id: usize) -> Option<CausalTree<T, I>> {
+        let event = self.get_logged_event(event_id)?;
+        let mut reactions: HashMap<usize, Vec<&Event<T, I>>> = HashMap::new();
+        for e in self.get_causal_log() {
+            if let Some(cause) = e.get_cause() {
+                reactions.entry(cause).or_default().push(e);
+            }
+        }
+        Some(grow_tree(event, &reactions, &mut HashSet::new()))
+    }
+}
+
+fn grow_tree<T: Tag, I: Id>(event: &Event<T, I>, reactions: &HashMap<usize, Vec<&Event<T, I>>>, visited: &mut HashSet<usize>) -> CausalTree<T, I> {
+    visited.insert(event.get_id());
+    let mut tree = CausalTree { event: event.clone(), reactions: vec![] };
+    for reaction in reactions.get(&event.get_id()).into_iter().flatten() {
+        if !visited.contains(&reaction.get_id()) {
+            tree.reactions.push(grow_tree(reaction, reactions, visited));
+        }
+    }
+    tree
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        event_handler::EventHandler as EH,
+        def_emitter::DefEmitter as DEm,
+        state_machine::{StateMachine, InvalidPolicy},
+        run::RunLimits,
+        tests::{TestTags::{self, *}, Recorder},
+    };
+    use super::*;
+
+    /// Handler reacting to T1 with T2 and to T2 with T3, T3 being recorded
+    fn reacting_handler() -> (EHRc<TestTags, usize>, Recorder) {
+        let eh = EH::<TestTags, usize>::new_ehrc();
+        let reactions = Recorder::new(vec![T3], None);
+        eh.borrow_mut().add_listener(reactions.as_lirc()).unwrap();
+        let mut sm = StateMachine::new((), InvalidPolicy::Ignore);
+        sm.add_transition((), T1, (), Some(T2));
+        sm.add_transition((), T2, (), Some(T3));
+        sm.register(&eh).unwrap();
+        (eh, reactions)
+    }
+
+    #[test]
+    fn reactions_record_their_cause() {
+        let (eh, reactions) = reacting_handler();
+        let em = DEm::<TestTags>::new_emrc(None);
+
+        eh.borrow_mut().emit(em.clone(), T1);
+        let root = eh.borrow().peek_next().unwrap().get_id();
+        eh.borrow_mut().run_until_empty(RunLimits::default());
+
+        let leaf = reactions.received.borrow()[0].clone();
+        assert_eq!(leaf.get_tag(), Some(T3));
+        let chain = eh.borrow().get_causal_chain(leaf.get_id());
+        assert_eq!(chain.iter().map(|e| e.get_tag()).collect::<Vec<_>>(), vec![Some(T1), Some(T2), Some(T3)]);
+        assert_eq!(chain[1].get_cause(), Some(root));
+        assert_eq!(eh.borrow().get_causal_root(leaf.get_id()).unwrap().get_id(), root);
+    }
+
+    #[test]
+    fn causal_tree() {
+        let (eh, _) = reacting_handler();
+        let em = DEm::<TestTags>::new_emrc(None);
+
+        eh.borrow_mut().emit(em.clone(), T1);
+        let root = eh.borrow().peek_next().unwrap().get_id();
+        eh.borrow_mut().run_until_empty(RunLimits::default());
+        eh.borrow_mut().emit(em.clone(), T3);
+        let other_root = eh.borrow().peek_next().unwrap().get_id();
+        eh.borrow_mut().consume_next_event();
+
+        let tree = eh.borrow().get_causal_tree(root).unwrap();
+        assert_eq!((tree.event_count(), tree.depth()), (3, 3));
+        assert_eq!(eh.borrow().get_causal_chain(other_root).len(), 1);
+        assert_eq!(eh.borrow().get_causal_tree(usize::MAX), None);
+    }
+
+    #[test]
+    fn looping_causes_are_walked_once() {
+        let eh = EH::<TestTags, usize>::new_ehrc();
+        let em = DEm::<TestTags>::new_emrc(None);
+        let a = Event::new(em.clone(), Some(T1));
+        let b = Event::new(em.clone(), Some(T2)).with_cause(a.get_id());
+        let a = a.with_cause(b.get_id());
+        let (a_id, b_id) = (a.get_id(), b.get_id());
+        eh.borrow_mut().push_events(Some(vec![a, b]));
+
+        let tree = eh.borrow().get_causal_tree(a_id).unwrap();
+        assert_eq!((tree.event_count(), tree.depth()), (2, 2));
+        assert_eq!(tree.reactions[0].event.get_id(), b_id);
+        assert_eq!(eh.borrow().get_causal_chain(a_id).len(), 2);
+    }
+
+    #[test]
+    fn log_capacity() {
+        let eh = EH::<TestTags, usize>::new_ehrc();
+        let em = DEm::<TestTags>::new_emrc(None);
+        eh.borrow_mut().set_causal_log_capacity(2);
+        for _ in 0..3 {
+            eh.borrow_mut().emit(em.clone(), T1);
+        }
+
+        let oldest = eh.borrow().get_stack()[0].get_id();
+        assert_eq!(eh.borrow().get_logged_event(oldest), None);
+        assert_eq!(eh.borrow().get_causal_log().len(), 2);
+
+        eh.borrow_mut().set_causal_log_capacity(0);
+        eh.borrow_mut().emit(em.clone(), T1);
+        assert!(eh.borrow().get_causal_log().is_empty());
+    }
+
+    #[test]
+    fn merged_events_take_the_pushed_cause() {
+        use crate::event_handler::CoalescePolicy;
+
+        let eh = EH::<TestTags, usize>::new_ehrc();
+        let em = DEm::<TestTags>::new_emrc(None);
+        eh.borrow_mut().set_coalesce_policy(CoalescePolicy::Merge(Rc::new(|_, new: &Event<TestTags, usize>| Some(Event::new(new.get_emitter(), Some(T3))))));
+        eh.borrow_mut().emit(em.clone(), T1);
+        eh.borrow_mut().push_event(Some(Event::new(em.clone(), Some(T2)).with_cause(7)));
+
+        let merged = eh.borrow().peek_next().unwrap().clone();
+        assert_eq!((merged.get_tag(), merged.get_cause()), (Some(T3), Some(7)));
+        assert_eq!(eh.borrow().get_logged_event(merged.get_id()), Some(&merged));
+    }
+}
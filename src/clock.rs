@@ -0,0 +1,82 @@
+use std::{cell::Cell, time::{Duration, SystemTime, UNIX_EPOCH}};
+use crate::prelude::*;
+
+/// Source of the creation timestamps of events
+pub trait Clock {
+    /// Time elapsed since the clock's epoch
+    fn now(&self) -> Duration;
+}
+
+/// Wall clock, counting from the Unix epoch
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default()
+    }
+}
+
+/// Clock that only moves when told to. Clones share the same time.
+#[derive(Debug, Clone, Default)]
+pub struct ManualClock {
+    now: Rc<Cell<Duration>>,
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Duration {
+        self.now.get()
+    }
+}
+
+impl ManualClock {
+    pub fn new(now: Duration) -> Self {
+        Self { now: Rc::new(Cell::new(now)) }
+    }
+    pub fn set(&self, now: Duration) {
+        self.now.set(now);
+    }
+    pub fn advance(&self, by: Duration) {
+        self.now.set(self.now.get() + by);
+    }
+}
+
+thread_local! {
+    static CLOCK: RefCell<Rc<dyn Clock>> = RefCell::new(Rc::new(SystemClock));
+}
+
+/// Sets the clock timestamping the events created on this thread from now on
+pub fn set_clock(clock: impl Clock + 'static) {
+    CLOCK.with(|c| *c.borrow_mut() = Rc::new(clock));
+}
+
+/// Time of the clock of this thread
+pub fn now() -> Duration {
+    // The clock is taken out first so it can create events itself
+    let clock = CLOCK.with(|c| c.borrow().clone());
+    clock.now()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{def_emitter::DefEmitter as DEm, event::Event, tests::TestTags::{self, *}};
+    use super::*;
+
+    #[test]
+    fn events_are_stamped_by_the_thread_clock() {
+        let time = ManualClock::new(Duration::from_secs(10));
+        set_clock(time.clone());
+        let em = DEm::<TestTags>::new_emrc(None);
+
+        let first = Event::<TestTags, usize>::new(em.clone(), Some(T1));
+        time.advance(Duration::from_millis(5));
+        let second = Event::<TestTags, usize>::new(em.clone(), Some(T1));
+        assert_eq!(first.get_timestamp(), Duration::from_secs(10));
+        assert_eq!(second.get_timestamp(), Duration::from_millis(10_005));
+
+        time.set(Duration::ZERO);
+        assert_eq!(now(), Duration::ZERO);
+        // Other threads keep the system clock
+        assert!(std::thread::spawn(now).join().unwrap() > Duration::from_secs(10));
+    }
+}
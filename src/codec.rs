@@ -0,0 +1,71 @@
+use std::time::Duration;
+use crate::{prelude::*, event::Event};
+
+/// Turns tags into bytes and back, for sending or storing events outside of the process
+pub trait TagCodec<T: Tag> {
+    fn encode(&self, tag: &T, buf: &mut Vec<u8>);
+    /// `None` if `bytes` is not a tag this codec knows
+    fn decode(&self, bytes: &[u8]) -> Option<T>;
+}
+
+fn read_u32(bytes: &[u8]) -> Option<(u32, &[u8])> {
+    let (n, rest) = bytes.split_at_checked(4)?;
+    Some((u32::from_le_bytes(n.try_into().unwrap()), rest))
+}
+
+fn read_str(bytes: &[u8]) -> Option<(String, &[u8])> {
+    let (len, rest) = read_u32(bytes)?;
+    let (s, rest) = rest.split_at_checked(len as usize)?;
+    Some((String::from_utf8(s.to_vec()).ok()?, rest))
+}
+
+/// Appends the creation timestamp and the headers of `event`: the `u64` seconds and
+/// `u32` nanoseconds of the timestamp, the `u32` header count, then each key and value
+/// as a `u32` length followed by UTF-8 bytes.
+/// Sequence numbers and handler ids only make sense within a process, so they are left out.
+pub(crate) fn encode_metadata<T: Tag, I: Id>(event: &Event<T, I>, buf: &mut Vec<u8>) {
+    let timestamp = event.get_timestamp();
+    buf.extend(timestamp.as_secs().to_le_bytes());
+    buf.extend(timestamp.subsec_nanos().to_le_bytes());
+    let headers: Vec<(&str, &str)> = event.get_headers().collect();
+    buf.extend((headers.len() as u32).to_le_bytes());
+    for s in headers.into_iter().flat_map(|(k, v)| [k, v]) {
+        buf.extend((s.len() as u32).to_le_bytes());
+        buf.extend(s.as_bytes());
+    }
+}
+
+/// Timestamp and headers of an event read back by `decode_metadata`
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Metadata {
+    timestamp: Duration,
+    headers: Vec<(String, String)>,
+}
+
+impl Metadata {
+    /// Gives `event` the timestamp and headers of the event they were read from
+    pub(crate) fn apply<T: Tag, I: Id>(self, mut event: Event<T, I>) -> Event<T, I> {
+        event.set_timestamp(self.timestamp);
+        for (key, value) in self.headers {
+            event.set_header(key, value);
+        }
+        event
+    }
+}
+
+/// Reads what `encode_metadata` wrote, along with the bytes after it
+pub(crate) fn decode_metadata(bytes: &[u8]) -> Option<(Metadata, &[u8])> {
+    let (secs, rest) = bytes.split_at_checked(8)?;
+    let (nanos, mut rest) = read_u32(rest)?;
+    let timestamp = Duration::new(u64::from_le_bytes(secs.try_into().unwrap()), nanos);
+    let (count, after) = read_u32(rest)?;
+    rest = after;
+    let mut headers = vec![];
+    for _ in 0..count {
+        let (key, after) = read_str(rest)?;
+        let (value, after) = read_str(after)?;
+        headers.push((key, value));
+        rest = after;
+    }
+    Some((Metadata { timestamp, headers }, rest))
+}
@@ -0,0 +1,174 @@
+use crate::{prelude::*, event::Event, listener::TriggerError};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DeadLetterReason {
+    Untagged,
+    /// No listener was triggered by the event, or nobody was left to take a reply
+    NoListeners,
+    /// Every triggered listener returned an error from `try_on_triggers`
+    AllFailed,
+    /// Every triggered listener returned an error, but at least one of them
+    /// kept the event to handle it later with `TriggerError::Deferred`
+    Deferred,
+    /// The stack was full and its overflow policy rejected the event, which was never broadcast
+    Overflow,
+}
+
+/// An event that was broadcast without reaching any listener, or that did not fit on the stack
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeadLetter<T: Tag, I: Id> {
+    pub event: Event<T, I>,
+    pub reason: DeadLetterReason,
+    /// Errors of the listeners that failed, empty unless `reason` is `AllFailed` or `Deferred`
+    pub errors: Vec<String>,
+}
+
+/// Where a handler puts its dead letters
+#[derive(Clone, Debug, Default)]
+pub enum DeadLetterSink<T: Tag, I: Id> {
+    /// Only count them
+    #[default]
+    Discard,
+    /// Keep them until they are taken from the handler
+    Collect,
+    /// Hand the events over to a listener, whatever its triggers
+    Forward(LiRC<T, I>),
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct DeadLetters<T: Tag, I: Id> {
+    sink: DeadLetterSink<T, I>,
+    collected: Vec<DeadLetter<T, I>>,
+    count: usize,
+}
+
+impl<T: Tag, I: Id> Default for DeadLetters<T, I> {
+    fn default() -> Self {
+        Self { sink: DeadLetterSink::default(), collected: Vec::new(), count: 0 }
+    }
+}
+
+impl<T: Tag, I: Id> DeadLetters<T, I> {
+    pub(crate) fn get_sink(&self) -> &DeadLetterSink<T, I> {
+        &self.sink
+    }
+    pub(crate) fn set_sink(&mut self, sink: DeadLetterSink<T, I>) {
+        self.sink = sink;
+    }
+    pub(crate) fn get_collected(&self) -> &Vec<DeadLetter<T, I>> {
+        &self.collected
+    }
+    pub(crate) fn take_collected(&mut self) -> Vec<DeadLetter<T, I>> {
+        std::mem::take(&mut self.collected)
+    }
+    pub(crate) fn get_count(&self) -> usize {
+        self.count
+    }
+    pub(crate) fn bury(&mut self, letter: DeadLetter<T, I>) {
+        self.count += 1;
+        match &self.sink {
+            DeadLetterSink::Discard => {}
+            DeadLetterSink::Collect => self.collected.push(letter),
+            DeadLetterSink::Forward(li) => li.borrow().on_triggers(vec![letter.event]),
+        }
+    }
+}
+
+/// Hands `event` to every listener in `listeners`, returning
+/// the dead letter if none of them took it
+pub(crate) fn deliver<'a, T: Tag, I: Id + 'a>(listeners: impl IntoIterator<Item = &'a LiRC<T, I>>, event: &Event<T, I>) -> Option<DeadLetter<T, I>> {
+    let mut delivered = false;
+    let mut deferred = false;
+    let mut errors = vec![];
+    for li in listeners {
+        match li.borrow().try_on_triggers(vec![event.clone()]) {
+            Ok(()) => delivered = true,
+            Err(e) => {
+                deferred |= matches!(e, TriggerError::Deferred(_));
+                errors.push(e.to_string());
+            }
+        }
+    }
+
+    if delivered {
+        None
+    } else if errors.is_empty() {
+        Some(DeadLetter { event: event.clone(), reason: DeadLetterReason::NoListeners, errors })
+    } else {
+        let reason = if deferred { DeadLetterReason::Deferred } else { DeadLetterReason::AllFailed };
+        Some(DeadLetter { event: event.clone(), reason, errors })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        event_handler::EventHandler as EH,
+        def_emitter::DefEmitter as DEm,
+        tests::{TestTags::{self, *}, Recorder},
+    };
+    use super::*;
+
+    /// Handler with a listener taking T1 and T2, and one failing T2 and T3
+    fn failing_handler() -> (EHRc<TestTags, usize>, Recorder) {
+        let eh = EH::<TestTags, usize>::new_ehrc();
+        let ok = Recorder::new(vec![T1, T2], None);
+        let mut failing = Recorder::new(vec![T2, T3], None);
+        failing.error = Some("boom");
+        eh.borrow_mut().add_listener(ok.as_lirc()).unwrap();
+        eh.borrow_mut().add_listener(failing.as_lirc()).unwrap();
+        (eh, ok)
+    }
+
+    #[test]
+    fn collect_undelivered_events() {
+        let (eh, ok) = failing_handler();
+        let em = DEm::<TestTags>::new_emrc(None);
+
+        eh.borrow_mut().set_dead_letter_sink(DeadLetterSink::Collect);
+        eh.borrow_mut().push_events(Some(vec![
+            Event::new(em.clone(), None),
+            Event::new(em.clone(), Some(T4(1))),
+            Event::new(em.clone(), Some(T3)),
+            Event::new(em.clone(), Some(T2)),
+            Event::new(em.clone(), Some(T1)),
+        ]));
+        for _ in 0..5 {
+            eh.borrow_mut().consume_next_event();
+        }
+
+        let letters = eh.borrow_mut().take_dead_letters();
+        assert_eq!(letters.iter().map(|l| l.reason).collect::<Vec<_>>(), vec![DeadLetterReason::AllFailed, DeadLetterReason::NoListeners, DeadLetterReason::Untagged]);
+        assert_eq!(letters[0].errors, vec!["boom".to_string()]);
+        assert_eq!(ok.received_tags(), vec![Some(T1), Some(T2)]);
+        assert!(eh.borrow().get_dead_letters().is_empty());
+    }
+
+    #[test]
+    fn forward_undelivered_events() {
+        let (eh, ok) = failing_handler();
+        let em = DEm::<TestTags>::new_emrc(None);
+        let fallback = Recorder::new(vec![], None);
+
+        eh.borrow_mut().set_dead_letter_sink(DeadLetterSink::Forward(fallback.as_lirc()));
+        eh.borrow_mut().emit(em.clone(), T5("lost"));
+        eh.borrow_mut().push_query(ok.as_emrc(), T4(2)).unwrap();
+        eh.borrow_mut().consume_next_event();
+        eh.borrow_mut().consume_next_event();
+
+        assert_eq!(fallback.received_tags(), vec![Some(T4(2)), Some(T5("lost"))]);
+        assert_eq!(eh.borrow().get_dead_letter_count(), 2);
+        assert!(eh.borrow().get_dead_letters().is_empty());
+    }
+
+    #[test]
+    fn discard_only_counts() {
+        let (eh, _) = failing_handler();
+        let em = DEm::<TestTags>::new_emrc(None);
+
+        eh.borrow_mut().emit(em.clone(), T3);
+        eh.borrow_mut().consume_next_event();
+        assert_eq!(eh.borrow().get_dead_letter_count(), 1);
+        assert!(eh.borrow().get_dead_letters().is_empty());
+    }
+}
@@ -1,17 +1,25 @@
 use crate::emit_obj::EmRC;
 use crate::listener::DefListener;
-use crate::{prelude::*};
+use crate::{prelude::*, event::Event};
 use crate::IDCOUNTER;
 
-#[derive(Debug, Clone, PartialEq)]
+/// Emitter that can be connected to handlers and push its events into them itself
+#[derive(Debug, Clone)]
 pub struct DefEmitter<T: Tag> {
     id: usize,
     def_tag: Option<T>,
+    handlers: Vec<Inbox<T, usize>>,
 }
 
-impl<T: Tag> Into<EmRC<usize>> for DefEmitter<T> {
-    fn into(self) -> EmRC<usize> {
-        EmRC(Rc::new(RefCell::new(self)))
+impl<T: Tag> PartialEq for DefEmitter<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id && self.def_tag == other.def_tag
+    }
+}
+
+impl<T: Tag> From<DefEmitter<T>> for EmRC<usize> {
+    fn from(em: DefEmitter<T>) -> Self {
+        EmRC(Rc::new(RefCell::new(em)))
     }
 }
 
@@ -23,18 +31,108 @@ impl<T: Tag> EmitObj<usize> for DefEmitter<T> {
 
 impl<T: Tag> DefEmitter<T> {
     pub fn new(def_tag: Option<T>) -> Self {
-        Self { id: IDCOUNTER.fetch_add(1, std::sync::atomic::Ordering::SeqCst), def_tag }
+        Self { id: IDCOUNTER.fetch_add(1, std::sync::atomic::Ordering::SeqCst), def_tag, handlers: Vec::new() }
     }
     pub fn new_emrc(def_tag: Option<T>) -> EmRC<usize> {
-        EmRC(Rc::new(RefCell::new(Self { id: IDCOUNTER.fetch_add(1, std::sync::atomic::Ordering::SeqCst), def_tag})))
+        EmRC(Rc::new(RefCell::new(Self::new(def_tag))))
+    }
+    pub fn as_emrc(&self) -> EmRC<usize> {
+        EmRC(Rc::new(RefCell::new(self.clone())))
     }
     pub fn into_emrc(self) -> EmRC<usize> {
         self.into()
     }
+    pub fn get_def_tag(&self) -> Option<T> {
+        self.def_tag
+    }
+    pub fn set_def_tag(&mut self, def_tag: Option<T>) {
+        self.def_tag = def_tag;
+    }
+    pub fn connect(&mut self, eh: &EHRc<T, usize>) {
+        let inbox = Inbox::new(eh);
+        if !self.handlers.contains(&inbox) {
+            self.handlers.push(inbox);
+        }
+    }
+    pub fn disconnect(&mut self, eh: &EHRc<T, usize>) -> bool {
+        let id = eh.borrow().get_id();
+        let len = self.handlers.len();
+        self.handlers.retain(|i| i.get_handler_id() != id);
+        self.handlers.len() != len
+    }
+    pub fn is_connected_to(&self, eh: &EHRc<T, usize>) -> bool {
+        let id = eh.borrow().get_id();
+        self.handlers.iter().any(|i| i.get_handler_id() == id && i.is_connected())
+    }
+    /// Ids of the handlers the emitter feeds, dropped handlers excluded
+    pub fn get_connected_handlers(&self) -> Vec<usize> {
+        self.handlers.iter().filter(|i| i.is_connected()).map(|i| i.get_handler_id()).collect()
+    }
+    /// Pushes `tag` into every connected handler, returns how many it reached
+    pub fn emit(&self, tag: T) -> usize {
+        #[cfg(test)]
+        println!("DefEmitter_{} emitted {:?}", self.id, tag);
+
+        let emitter = self.as_emrc();
+        let connected: Vec<&Inbox<T, usize>> = self.handlers.iter().filter(|i| i.is_connected()).collect();
+        for inbox in &connected {
+            inbox.push(Event::new(emitter.clone(), Some(tag)));
+        }
+        connected.len()
+    }
+    /// Emits the default tag, failing if the emitter has none
+    pub fn emit_default(&self) -> Result<usize, String> {
+        match self.def_tag {
+            Some(tag) => Ok(self.emit(tag)),
+            None => Err(format!("DefEmitter_{} has no default tag", self.id)),
+        }
+    }
 }
 
 impl<T: Tag> PartialEq<DefListener<T>> for DefEmitter<T> {
     fn eq(&self, other: &DefListener<T>) -> bool {
         self.get_id() == other.get_id()
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use crate::{event_handler::EventHandler, tests::TestTags::{self, *}};
+    use super::*;
+
+    type EH = EventHandler<TestTags, usize>;
+
+    #[test]
+    fn emit_into_connected_handlers() {
+        let (ui, log) = (EH::new_ehrc(), EH::new_ehrc());
+        let mut button = DefEmitter::new(Some(T1));
+        button.connect(&ui);
+        button.connect(&ui);
+        button.connect(&log);
+        assert_eq!(button.get_connected_handlers(), vec![ui.borrow().get_id(), log.borrow().get_id()]);
+
+        assert_eq!(button.emit(T4(2)), 2);
+        assert_eq!(button.emit_default(), Ok(2));
+        assert_eq!(ui.borrow().get_stack_tags(), vec![Some(T4(2)), Some(T1)]);
+        assert_eq!(log.borrow().get_stack_emitters(), vec![button.as_emrc(), button.as_emrc()]);
+    }
+
+    #[test]
+    fn disconnect() {
+        let (ui, log) = (EH::new_ehrc(), EH::new_ehrc());
+        let mut button = DefEmitter::new(None);
+        button.connect(&ui);
+        button.connect(&log);
+        assert!(button.emit_default().is_err());
+
+        assert!(button.disconnect(&log));
+        assert!(!button.disconnect(&log));
+        assert!(!button.is_connected_to(&log));
+        assert_eq!(button.emit(T3), 1);
+        assert_eq!(log.borrow().get_stack_len(), 0);
+
+        // Dropped handlers are no longer reached
+        drop(ui);
+        assert_eq!(button.emit(T3), 0);
+        assert_eq!(button.get_connected_handlers(), vec![]);
+    }
+}
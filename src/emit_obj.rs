@@ -1,8 +1,8 @@
 use crate::prelude::*;
 
-pub trait Id = PartialEq + Debug + Clone;
+pub trait Id = PartialEq + Debug + Clone + 'static;
 
-///
+/// Any object that can be identified as the source of an event
 pub trait EmitObj<I: Id> {
     fn get_id(&self) -> I;
 }
@@ -1,36 +1,130 @@
-use crate::prelude::*;
+use std::{collections::BTreeMap, sync::atomic::{AtomicU64, Ordering}, time::Duration};
+use crate::{prelude::*, clock, IDCOUNTER};
+
+static SEQUENCE: AtomicU64 = AtomicU64::new(0);
+
+type Headers = BTreeMap<String, String>;
 
 pub trait Tag = Debug + PartialEq + Copy + 'static;
 
 pub struct Event<T: Tag, I: Id> {
+    id: usize,
+    cause: Option<usize>,
     emitter: EmRC<I>,
     tag: Option<T>,
+    correlation: Option<usize>,
+    reply_to: Option<EmRC<I>>,
+    depth: usize,
+    /// Handlers a `Router` forwarded the event through
+    trail: Option<Rc<[usize]>>,
+    /// Id of the `UndoManager` whose undo or redo emitted the event
+    replayed_by: Option<usize>,
+    sequence: u64,
+    timestamp: Duration,
+    handler_id: Option<usize>,
+    /// Shared between clones until one of them changes its headers
+    headers: Option<Rc<Headers>>,
 }
 
 impl<T: Tag, I: Id> Clone for Event<T, I> {
     fn clone(&self) -> Self {
-        Event { emitter: self.emitter.clone(), tag: self.tag }
+        Event {
+            id: self.id,
+            cause: self.cause,
+            emitter: self.emitter.clone(),
+            tag: self.tag,
+            correlation: self.correlation,
+            reply_to: self.reply_to.clone(),
+            depth: self.depth,
+            sequence: self.sequence,
+            timestamp: self.timestamp,
+            handler_id: self.handler_id,
+            headers: self.headers.clone(),
+            trail: self.trail.clone(),
+            replayed_by: self.replayed_by,
+        }
     }
 }
 
 impl<T: Tag, I: Id> Debug for Event<T, I> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Event")
+            .field("id", &self.id)
+            .field("cause", &self.cause)
             .field("EmitObj id", &self.emitter.borrow().get_id())
             .field("tag", &self.tag)
+            .field("correlation", &self.correlation)
+            .field("reply_to", &self.reply_to.as_ref().map(|e| e.borrow().get_id()))
+            .field("depth", &self.depth)
+            .field("sequence", &self.sequence)
+            .field("timestamp", &self.timestamp)
+            .field("handler_id", &self.handler_id)
+            .field("headers", &self.headers)
+            .field("trail", &self.trail)
+            .field("replayed_by", &self.replayed_by)
             .finish()
     }
 }
 
+/// Events are equal when they are the same emission, that is clones of each other.
+/// `same_content` compares what was emitted instead.
 impl<T: Tag, I: Id> PartialEq for Event<T, I> {
     fn eq(&self, other: &Self) -> bool {
-        self.emitter.borrow().get_id() == other.emitter.borrow().get_id() && self.tag == other.tag
+        self.id == other.id
     }
 }
 
-impl<'a, T: Tag, I: Id> Event<T, I> {
+fn next_event_id() -> usize {
+    IDCOUNTER.fetch_add(1, std::sync::atomic::Ordering::SeqCst)
+}
+
+fn next_sequence() -> u64 {
+    SEQUENCE.fetch_add(1, Ordering::SeqCst)
+}
+
+impl<T: Tag, I: Id> Event<T, I> {
     pub fn new(emitter: EmRC<I>, tag: Option<T>) -> Self {
-        Self { emitter, tag }
+        Self {
+            id: next_event_id(),
+            cause: None,
+            emitter,
+            tag,
+            correlation: None,
+            reply_to: None,
+            depth: 0,
+            sequence: next_sequence(),
+            timestamp: clock::now(),
+            handler_id: None,
+            headers: None,
+            trail: None,
+            replayed_by: None,
+        }
+    }
+    /// Query event listeners can answer through `IListener::on_query`
+    pub fn new_query(emitter: EmRC<I>, tag: T, correlation: usize) -> Self {
+        Self { correlation: Some(correlation), ..Self::new(emitter, Some(tag)) }
+    }
+    /// Reply to `query`, addressed to the query's emitter
+    pub fn new_reply(emitter: EmRC<I>, tag: T, query: &Event<T, I>) -> Self {
+        Self { cause: Some(query.id), correlation: query.correlation, reply_to: Some(query.get_emitter()), ..Self::new(emitter, Some(tag)) }
+    }
+    /// Marks the event as a reaction to the event with id `cause`
+    pub fn with_cause(mut self, cause: usize) -> Self {
+        self.cause = Some(cause);
+        self
+    }
+    /// Adds a user header, replacing any header with the same key
+    pub fn with_header(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.set_header(key, value);
+        self
+    }
+    /// Unique id, shared only with clones of this event
+    pub fn get_id(&self) -> usize {
+        self.id
+    }
+    /// Id of the event this one was emitted in reaction to
+    pub fn get_cause(&self) -> Option<usize> {
+        self.cause
     }
     pub fn get_emitter(&self) -> EmRC<I> {
         self.emitter.clone()
@@ -38,4 +132,147 @@ impl<'a, T: Tag, I: Id> Event<T, I> {
     pub fn get_tag(&self) -> Option<T> {
         self.tag
     }
-}
\ No newline at end of file
+    pub fn get_correlation_id(&self) -> Option<usize> {
+        self.correlation
+    }
+    pub fn get_reply_to(&self) -> Option<EmRC<I>> {
+        self.reply_to.clone()
+    }
+    pub fn is_query(&self) -> bool {
+        self.correlation.is_some() && self.reply_to.is_none()
+    }
+    pub fn is_reply(&self) -> bool {
+        self.reply_to.is_some()
+    }
+    /// How many dispatches deep the event was emitted,
+    /// 0 for events pushed from outside of a broadcast
+    pub fn get_depth(&self) -> usize {
+        self.depth
+    }
+    /// Whether both events have the same emitter and tag, whichever emission they are
+    pub fn same_content(&self, other: &Self) -> bool {
+        self.emitter.borrow().get_id() == other.emitter.borrow().get_id() && self.tag == other.tag
+    }
+    /// Position of the event among every event created, in creation order
+    pub fn get_sequence(&self) -> u64 {
+        self.sequence
+    }
+    /// Creation time, from the clock set with `clock::set_clock`
+    pub fn get_timestamp(&self) -> Duration {
+        self.timestamp
+    }
+    /// Id of the handler the event was last pushed into
+    pub fn get_handler_id(&self) -> Option<usize> {
+        self.handler_id
+    }
+    pub fn get_header(&self, key: &str) -> Option<&str> {
+        self.headers.as_ref()?.get(key).map(String::as_str)
+    }
+    pub fn get_headers(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.headers.iter().flat_map(|h| h.iter()).map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+    pub fn set_header(&mut self, key: impl Into<String>, value: impl Into<String>) {
+        Rc::make_mut(self.headers.get_or_insert_default()).insert(key.into(), value.into());
+    }
+    pub fn remove_header(&mut self, key: &str) -> Option<String> {
+        Rc::make_mut(self.headers.as_mut()?).remove(key)
+    }
+    /// Adds the headers of `other` this event does not have
+    pub(crate) fn inherit_headers(&mut self, other: &Self) {
+        let Some(inherited) = &other.headers else { return };
+        match &mut self.headers {
+            Some(own) => {
+                let own = Rc::make_mut(own);
+                for (key, value) in inherited.iter() {
+                    own.entry(key.clone()).or_insert_with(|| value.clone());
+                }
+            }
+            None => self.headers = Some(inherited.clone()),
+        }
+    }
+    pub(crate) fn set_timestamp(&mut self, timestamp: Duration) {
+        self.timestamp = timestamp;
+    }
+    pub(crate) fn set_handler_id(&mut self, handler_id: usize) {
+        self.handler_id = Some(handler_id);
+    }
+    pub(crate) fn set_depth(&mut self, depth: usize) {
+        self.depth = depth;
+    }
+    pub(crate) fn set_cause(&mut self, cause: usize) {
+        self.cause = Some(cause);
+    }
+    /// Ids of the handlers a `Router` forwarded the event through, starting with the one
+    /// it was first consumed by. Empty for events that were not forwarded.
+    pub fn get_trail(&self) -> &[usize] {
+        self.trail.as_deref().unwrap_or_default()
+    }
+    pub(crate) fn set_trail(&mut self, trail: &[usize]) {
+        self.trail = (!trail.is_empty()).then(|| trail.into());
+    }
+    pub(crate) fn get_replayed_by(&self) -> Option<usize> {
+        self.replayed_by
+    }
+    pub(crate) fn set_replayed_by(&mut self, manager: Option<usize>) {
+        self.replayed_by = manager;
+    }
+    /// Copy of the event as forwarded along `trail`, caused by it and with a new id, sequence number and tag.
+    /// It keeps the emitter, timestamp and headers of the event. Forwarded queries and replies become plain events.
+    pub(crate) fn forward(&self, tag: Option<T>, trail: &[usize]) -> Self {
+        let mut forwarded = Self { id: next_event_id(), sequence: next_sequence(), cause: Some(self.id), tag, correlation: None, reply_to: None, ..self.clone() };
+        forwarded.set_trail(trail);
+        forwarded
+    }
+}
+#[cfg(test)]
+mod tests {
+    use crate::{def_emitter::DefEmitter as DEm, event_handler::EventHandler, tests::TestTags::{self, *}};
+    use super::*;
+
+    type Ev = Event<TestTags, usize>;
+
+    #[test]
+    fn equality_is_by_emission() {
+        let em = DEm::<TestTags>::new_emrc(None);
+        let first = Ev::new(em.clone(), Some(T1));
+        let second = Ev::new(em.clone(), Some(T1));
+
+        assert_ne!(first, second);
+        assert!(first.same_content(&second));
+        assert_eq!(first.clone(), first);
+        assert!(!first.same_content(&Ev::new(em.clone(), Some(T2))));
+        assert!(!first.same_content(&Ev::new(DEm::<TestTags>::new_emrc(None), Some(T1))));
+        assert!(first.get_sequence() < second.get_sequence());
+    }
+
+    #[test]
+    fn headers() {
+        let em = DEm::<TestTags>::new_emrc(None);
+        let event = Ev::new(em.clone(), Some(T1)).with_header("user", "ob");
+        let mut copy = event.clone();
+
+        copy.set_header("trace", "42");
+        assert_eq!(copy.get_header("user"), Some("ob"));
+        assert_eq!(event.get_header("trace"), None);
+        assert_eq!(copy.get_headers().collect::<Vec<_>>(), vec![("trace", "42"), ("user", "ob")]);
+        assert_eq!(copy.remove_header("user"), Some("ob".to_string()));
+        assert_eq!(copy.remove_header("user"), None);
+        assert_eq!(Ev::new(em.clone(), None).get_headers().count(), 0);
+
+        let mut merged = Ev::new(em.clone(), Some(T2)).with_header("trace", "7");
+        merged.inherit_headers(&copy.with_header("user", "ann"));
+        assert_eq!(merged.get_headers().collect::<Vec<_>>(), vec![("trace", "7"), ("user", "ann")]);
+    }
+
+    #[test]
+    fn pushed_events_get_the_handler_id() {
+        let mut eh = EventHandler::<TestTags, usize>::new();
+        let em = DEm::<TestTags>::new_emrc(None);
+        let event = Ev::new(em.clone(), Some(T1));
+        assert_eq!(event.get_handler_id(), None);
+
+        eh.push_event(Some(event));
+        eh.emit(em.clone(), T2);
+        assert!(eh.get_stack().iter().all(|e| e.get_handler_id() == Some(eh.get_id())));
+    }
+}
@@ -1,13 +1,158 @@
+use std::{collections::{HashMap, VecDeque}, mem::Discriminant, rc::Weak};
 use crate::prelude::*;
 use crate::{event::Event, sub_event_handler::SubEventHandler, IDCOUNTER};
+use crate::listener::TRIGGERS_GENERATION;
+use crate::dead_letter::{DeadLetter, DeadLetterReason, DeadLetterSink, DeadLetters};
+use crate::injector::Injection;
+use crate::wal::{Journal, StackOp};
+use crate::actor::{self, Actors};
+use crate::parallel::ParallelDispatch;
+use crate::overflow::{Bounds, Room, StackFull};
+use crate::sticky::Retained;
 
-///
-#[derive(Clone)]
+/// How `broadcast_event` finds the listeners triggered by an event
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum DispatchMode {
+    /// Ask every listener in turn
+    #[default]
+    Linear,
+    /// Only ask listeners with a trigger of the same enum variant as the event's tag.
+    /// Relies on `has_trigger` never accepting a tag whose variant is missing
+    /// from `get_triggers`. Listeners whose triggers change after being added
+    /// must call `listener::triggers_changed`, the index is then rebuilt before the
+    /// next broadcast and lookups fall back to `Linear` until it is.
+    /// Tags that are structs, like `Topic`, or enums with a single variant have one
+    /// discriminant, so every listener shares a bucket and this behaves as `Linear`.
+    Indexed,
+}
+
+pub type Observer<T, I> = Rc<dyn Fn(&Event<T, I>)>;
+
+/// Observer registered with `EventHandler::observe`, removed once the handle is dropped.
+/// A handle dropped while the handler is borrowed, e.g. mid-broadcast, queues the removal,
+/// and the observer stops running as soon as the handler gets to it.
+pub struct ObserverHandle<T: Tag, I: Id> {
+    id: usize,
+    handler: Weak<RefCell<EventHandler<T, I>>>,
+    removals: Weak<RefCell<Vec<usize>>>,
+}
+
+impl<T: Tag, I: Id> Debug for ObserverHandle<T, I> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ObserverHandle")
+            .field("id", &self.id)
+            .field("handler id", &self.handler.upgrade().and_then(|eh| eh.try_borrow().ok().map(|eh| eh.get_id())))
+            .finish()
+    }
+}
+
+impl<T: Tag, I: Id> ObserverHandle<T, I> {
+    /// Id of the observer, as returned by `add_observer`
+    pub fn get_id(&self) -> usize {
+        self.id
+    }
+}
+
+impl<T: Tag, I: Id> Drop for ObserverHandle<T, I> {
+    fn drop(&mut self) {
+        let Some(eh) = self.handler.upgrade() else { return };
+        if let Ok(mut eh) = eh.try_borrow_mut() {
+            eh.remove_observer(self.id);
+        } else if let Some(removals) = self.removals.upgrade() {
+            removals.borrow_mut().push(self.id);
+        }
+    }
+}
+
+type MergeFn<T, I> = Rc<dyn Fn(&Event<T, I>, &Event<T, I>) -> Option<Event<T, I>>>;
+
+/// How `push_event` folds a new event into the events already pending.
+/// Queries and replies are never coalesced.
+#[derive(Clone, Default)]
+pub enum CoalescePolicy<T: Tag, I: Id> {
+    #[default]
+    Off,
+    /// Drop the new event if one with the same emitter and tag is pending
+    DropDuplicates,
+    /// Replace the pending event from the same emitter with the new one
+    LatestPerEmitter,
+    /// `merge(pending, new)` is tried against the pending events, most recent first,
+    /// and the first event it returns takes the place of that pending event,
+    /// along with the cause, router trail and undo replay mark of `new` if it has none of its own
+    Merge(MergeFn<T, I>),
+}
+
+impl<T: Tag, I: Id> Debug for CoalescePolicy<T, I> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CoalescePolicy::Off => write!(f, "Off"),
+            CoalescePolicy::DropDuplicates => write!(f, "DropDuplicates"),
+            CoalescePolicy::LatestPerEmitter => write!(f, "LatestPerEmitter"),
+            CoalescePolicy::Merge(_) => write!(f, "Merge"),
+        }
+    }
+}
+
+/// Pushed events a new handler keeps in its causal log
+pub const DEFAULT_CAUSAL_LOG_CAPACITY: usize = 256;
+
+/// Stack-based event handler broadcasting consumed events to its listeners
 pub struct EventHandler<T: Tag, I: Id> {
     id: usize,
     stack: Vec<Event<T, I>>,
     prev_event: Option<Event<T, I>>,
     listeners: Vec<LiRC<T, I>>,
+    emitters: Vec<EmRC<I>>,
+    inbox: Rc<RefCell<Vec<Event<T, I>>>>,
+    dispatch_mode: DispatchMode,
+    index: HashMap<Discriminant<T>, Vec<usize>>,
+    /// `listener::TRIGGERS_GENERATION` when the index was last built
+    index_generation: usize,
+    coalesce_policy: CoalescePolicy<T, I>,
+    coalesced: usize,
+    /// Id and depth of the event being broadcast
+    dispatching: Option<(usize, usize)>,
+    causal_log: VecDeque<Event<T, I>>,
+    causal_log_capacity: usize,
+    dead_letters: DeadLetters<T, I>,
+    observers: Vec<(usize, Observer<T, I>)>,
+    /// Observers whose handles were dropped while the handler was borrowed
+    observer_removals: Rc<RefCell<Vec<usize>>>,
+    injection: Option<Injection<T, I>>,
+    journal: Option<Rc<RefCell<dyn Journal<T, I>>>>,
+    actors: Option<Actors<T, I>>,
+    /// State of the `ParallelDispatcher` handing broadcast events to its listeners
+    parallel: Option<Weak<RefCell<dyn ParallelDispatch<T, I>>>>,
+    bounds: Bounds<T, I>,
+    retained: Retained<T, I>,
+}
+
+/// Clones get a new id and their own inbox, and share listeners and emitters with the original.
+/// Observers, injectors, parallel dispatchers and the write-ahead log stay with the original, and
+/// clones in actor mode start with empty mailboxes.
+impl<T: Tag, I: Id> Clone for EventHandler<T, I> {
+    fn clone(&self) -> Self {
+        EventHandler {
+            stack: self.stack.clone(),
+            prev_event: self.prev_event.clone(),
+            listeners: self.listeners.clone(),
+            emitters: self.emitters.clone(),
+            inbox: Rc::new(RefCell::new(self.inbox.borrow().clone())),
+            dispatch_mode: self.dispatch_mode,
+            index: self.index.clone(),
+            index_generation: self.index_generation,
+            coalesce_policy: self.coalesce_policy.clone(),
+            coalesced: self.coalesced,
+            dispatching: self.dispatching,
+            causal_log: self.causal_log.clone(),
+            causal_log_capacity: self.causal_log_capacity,
+            dead_letters: self.dead_letters.clone(),
+            actors: self.get_actor_mode().map(Actors::new),
+            bounds: self.bounds.clone(),
+            retained: self.retained.clone(),
+            ..Self::new()
+        }
+    }
 }
 
 impl<T: Tag, I: Id> Debug for EventHandler<T, I> {
@@ -17,6 +162,19 @@ impl<T: Tag, I: Id> Debug for EventHandler<T, I> {
             .field("stack", &self.stack)
             .field("prev_event", &self.prev_event)
             .field("listener ids", &self.listeners.iter().map(|l| l.borrow().get_id()).collect::<Vec<I>>())
+            .field("emitter ids", &self.emitters.iter().map(|e| e.borrow().get_id()).collect::<Vec<I>>())
+            .field("inbox", &self.inbox.borrow())
+            .field("dispatch_mode", &self.dispatch_mode)
+            .field("coalesce_policy", &self.coalesce_policy)
+            .field("coalesced", &self.coalesced)
+            .field("dead_letters", &self.dead_letters)
+            .field("observer ids", &self.observers.iter().map(|(id, _)| *id).collect::<Vec<usize>>())
+            .field("observer_removals", &self.observer_removals.borrow())
+            .field("journal", &self.journal.as_ref().map(|j| j.borrow().get_stats()))
+            .field("actors", &self.actors)
+            .field("parallel mode", &self.is_parallel_mode())
+            .field("bounds", &self.bounds)
+            .field("retained", &self.retained)
             .finish()
     }
 }
@@ -41,10 +199,15 @@ impl<'a, P: EHParent<T, I> + Debug, T: Tag, I: Id> PartialEq<SubEventHandler<'a,
     }
 }
 
-impl<T: Tag, I: Id> Into<EHRc<T, I>> for EventHandler<T, I> {
-    fn into(self) -> EHRc<T, I> {
-        use std::{rc::Rc, cell::RefCell};
-        Rc::new(RefCell::new(self))
+impl<T: Tag, I: Id> From<EventHandler<T, I>> for EHRc<T, I> {
+    fn from(eh: EventHandler<T, I>) -> Self {
+        Rc::new(RefCell::new(eh))
+    }
+}
+
+impl<T: Tag, I: Id> Default for EventHandler<T, I> {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -55,6 +218,25 @@ impl<T: Tag, I: Id> EventHandler<T, I> {
             stack: Vec::new(),
             prev_event: None,
             listeners: Vec::new(),
+            emitters: Vec::new(),
+            inbox: Rc::new(RefCell::new(Vec::new())),
+            dispatch_mode: DispatchMode::default(),
+            index: HashMap::new(),
+            index_generation: TRIGGERS_GENERATION.load(std::sync::atomic::Ordering::SeqCst),
+            coalesce_policy: CoalescePolicy::default(),
+            coalesced: 0,
+            dispatching: None,
+            causal_log: VecDeque::new(),
+            causal_log_capacity: DEFAULT_CAUSAL_LOG_CAPACITY,
+            dead_letters: DeadLetters::default(),
+            observers: Vec::new(),
+            observer_removals: Rc::new(RefCell::new(Vec::new())),
+            injection: None,
+            journal: None,
+            actors: None,
+            parallel: None,
+            bounds: Bounds::default(),
+            retained: Retained::default(),
         }
     }
     pub fn new_ehrc() -> Rc<RefCell<Self>> {
@@ -69,13 +251,66 @@ impl<T: Tag, I: Id> EventHandler<T, I> {
     pub fn get_id(&self) -> usize {
         self.id
     }
+    /// Events a full stack rejects become `Overflow` dead letters, see `try_push_event`
     pub fn push_event(&mut self, event: Option<Event<T, I>>) {
         if let Some(e) = event {
+            self.push_or_bury(e, false);
+        }
+    }
+    /// Fails with the event if the stack is full and the overflow policy rejects it
+    pub fn try_push_event(&mut self, event: Event<T, I>) -> Result<(), StackFull<T, I>> {
+        self.push(event, false)
+    }
+    fn push_or_bury(&mut self, e: Event<T, I>, front: bool) {
+        if let Err(full) = self.push(e, front) {
+            let errors = vec![full.to_string()];
+            self.bury(DeadLetter { event: *full.event, reason: DeadLetterReason::Overflow, errors });
+        }
+    }
+    /// Pushes `e` on top of the stack, or at the front of it
+    fn push(&mut self, mut e: Event<T, I>, front: bool) -> Result<(), StackFull<T, I>> {
+        if let Some((cause, depth)) = self.dispatching {
+            e.set_depth(depth + 1);
+            if e.get_cause().is_none() {
+                e.set_cause(cause);
+            }
+        }
+        let Some(e) = self.coalesce(e) else { return Ok(()) };
+        let Some(mut e) = self.make_room(e)? else { return Ok(()) };
+        e.set_handler_id(self.id);
+        self.log_cause(&e);
+        self.journal(if front { StackOp::PushFront(&e) } else { StackOp::Push(&e) });
+
+        if front {
+            #[cfg(test)]
+            println!("{} pushed an event to the front of stack: {:?}", self, e);
+
+            self.stack.insert(0, e);
+        } else {
             #[cfg(test)]
             println!("{} pushed an event to stack: {:?}", self, e);
-    
+
             self.stack.push(e);
         }
+        Ok(())
+    }
+    /// Returns `event` if it still has to go onto the stack
+    fn make_room(&mut self, event: Event<T, I>) -> Result<Option<Event<T, I>>, StackFull<T, I>> {
+        match self.bounds.make_room(&mut self.stack, event) {
+            Room::Push(e) => Ok(Some(e)),
+            Room::Evicted(e, evicted) => {
+                self.journal(StackOp::Remove(evicted));
+                Ok(Some(e))
+            }
+            Room::Dropped => Ok(None),
+            Room::Full(full) => Err(full),
+        }
+    }
+    pub(crate) fn get_bounds(&self) -> &Bounds<T, I> {
+        &self.bounds
+    }
+    pub(crate) fn get_bounds_mut(&mut self) -> &mut Bounds<T, I> {
+        &mut self.bounds
     }
     pub fn push_events(&mut self, events: Option<Vec<Event<T, I>>>) {
         if let Some(events) = events {
@@ -84,6 +319,121 @@ impl<T: Tag, I: Id> EventHandler<T, I> {
             }
         }
     }
+    pub(crate) fn get_retained(&self) -> &Retained<T, I> {
+        &self.retained
+    }
+    pub(crate) fn get_retained_mut(&mut self) -> &mut Retained<T, I> {
+        &mut self.retained
+    }
+    pub fn get_coalesce_policy(&self) -> &CoalescePolicy<T, I> {
+        &self.coalesce_policy
+    }
+    pub fn set_coalesce_policy(&mut self, policy: CoalescePolicy<T, I>) {
+        self.coalesce_policy = policy;
+    }
+    /// Number of pushed events merged into or dropped in favour of a pending event
+    pub fn get_coalesced_count(&self) -> usize {
+        self.coalesced
+    }
+    /// Returns `event` if it still has to go onto the stack
+    fn coalesce(&mut self, event: Event<T, I>) -> Option<Event<T, I>> {
+        if event.get_correlation_id().is_some() {
+            return Some(event);
+        }
+        let emitter_id = event.get_emitter().borrow().get_id();
+        let same_emitter = |p: &Event<T, I>| p.get_correlation_id().is_none() && p.get_emitter().borrow().get_id() == emitter_id;
+
+        match &self.coalesce_policy {
+            CoalescePolicy::Off => return Some(event),
+            CoalescePolicy::DropDuplicates => {
+                if !self.stack.iter().any(|p| same_emitter(p) && p.same_content(&event)) {
+                    return Some(event);
+                }
+            }
+            CoalescePolicy::LatestPerEmitter => match self.stack.iter().rposition(same_emitter) {
+                Some(pos) => self.replace_event(pos, event.clone(), &event),
+                None => return Some(event),
+            },
+            CoalescePolicy::Merge(merge) => {
+                let merged = self.stack.iter().enumerate().rev()
+                    .filter(|(_, p)| p.get_correlation_id().is_none())
+                    .find_map(|(pos, p)| merge(p, &event).map(|m| (pos, m)));
+                match merged {
+                    Some((pos, m)) => self.replace_event(pos, m, &event),
+                    None => return Some(event),
+                }
+            }
+        }
+
+        #[cfg(test)]
+        println!("{} coalesced an event from {:?}", self, emitter_id);
+
+        self.coalesced += 1;
+        None
+    }
+    /// Puts `event` in place of a pending event, as if `pushed`, the event it comes from, had been pushed:
+    /// it takes the cause and depth of `pushed` unless it has a cause of its own,
+    /// the router trail and undo replay mark of `pushed` unless it has them,
+    /// and the headers of `pushed` it does not have
+    fn replace_event(&mut self, pos: usize, mut event: Event<T, I>, pushed: &Event<T, I>) {
+        if event.get_cause().is_none() && let Some(cause) = pushed.get_cause() {
+            event.set_cause(cause);
+            event.set_depth(pushed.get_depth());
+        }
+        if event.get_trail().is_empty() {
+            event.set_trail(pushed.get_trail());
+        }
+        if event.get_replayed_by().is_none() {
+            event.set_replayed_by(pushed.get_replayed_by());
+        }
+        event.inherit_headers(pushed);
+        event.set_handler_id(self.id);
+        self.log_cause(&event);
+        self.journal(StackOp::Replace(self.stack[pos].get_id(), &event));
+        self.stack[pos] = event;
+    }
+    fn journal(&self, op: StackOp<T, I>) {
+        if let Some(journal) = &self.journal {
+            journal.borrow_mut().record(op);
+        }
+    }
+    pub(crate) fn get_journal(&self) -> Option<&Rc<RefCell<dyn Journal<T, I>>>> {
+        self.journal.as_ref()
+    }
+    pub(crate) fn set_journal(&mut self, journal: Option<Rc<RefCell<dyn Journal<T, I>>>>) {
+        self.journal = journal;
+    }
+    /// Keeps the last `capacity` events that made it onto the stack so causal chains can be walked,
+    /// a capacity of 0 turns the log off. Defaults to `DEFAULT_CAUSAL_LOG_CAPACITY`.
+    pub fn set_causal_log_capacity(&mut self, capacity: usize) {
+        self.causal_log_capacity = capacity;
+        while self.causal_log.len() > capacity {
+            self.causal_log.pop_front();
+        }
+    }
+    pub(crate) fn get_causal_log(&self) -> &VecDeque<Event<T, I>> {
+        &self.causal_log
+    }
+    fn log_cause(&mut self, event: &Event<T, I>) {
+        if self.causal_log_capacity == 0 {
+            return;
+        }
+        if self.causal_log.len() == self.causal_log_capacity {
+            self.causal_log.pop_front();
+        }
+        self.causal_log.push_back(event.clone());
+    }
+    pub(crate) fn get_inbox_queue(&self) -> Rc<RefCell<Vec<Event<T, I>>>> {
+        self.inbox.clone()
+    }
+    /// Moves events queued through an `Inbox` onto the stack,
+    /// returns how many were moved
+    pub fn drain_inbox(&mut self) -> usize {
+        let queued = std::mem::take(&mut *self.inbox.borrow_mut());
+        let n = queued.len();
+        self.push_events(Some(queued));
+        n
+    }
     pub fn get_stack(&self) -> &Vec<Event<T, I>> {
         &self.stack
     }
@@ -99,17 +449,98 @@ impl<T: Tag, I: Id> EventHandler<T, I> {
     pub fn stack_has_emitter(&self, emitter: &EmRC<I>) -> bool {
         self.get_stack_emitters().contains(emitter)
     }
+    /// Pending event `n` places away from being consumed, `peek_nth(0)` is the next one
+    pub fn peek_nth(&self, n: usize) -> Option<&Event<T, I>> {
+        self.stack.iter().rev().nth(n)
+    }
+    /// Iterates over pending events in the order they will be consumed
+    pub fn iter_stack(&self) -> impl DoubleEndedIterator<Item = &Event<T, I>> {
+        self.stack.iter().rev()
+    }
+    /// Puts `event` at the front of the stack, to be consumed after every pending event.
+    /// Otherwise it is pushed like with `push_event`, coalescing included.
+    pub fn push_event_front(&mut self, event: Event<T, I>) {
+        self.push_or_bury(event, true);
+    }
+    /// Like `push_event_front`, but fails with the event if the full stack rejects it
+    pub fn try_push_event_front(&mut self, event: Event<T, I>) -> Result<(), StackFull<T, I>> {
+        self.push(event, true)
+    }
+    /// Keeps only the pending events matching `keep`, returns how many were removed
+    pub fn retain_events(&mut self, mut keep: impl FnMut(&Event<T, I>) -> bool) -> usize {
+        let mut removed = vec![];
+        self.stack.retain(|e| keep(e) || {
+            removed.push(e.get_id());
+            false
+        });
+        for id in &removed {
+            self.journal(StackOp::Remove(*id));
+        }
+        removed.len()
+    }
+    /// Removes the pending events matching `remove`, returns how many were removed
+    pub fn remove_events_where(&mut self, mut remove: impl FnMut(&Event<T, I>) -> bool) -> usize {
+        self.retain_events(|e| !remove(e))
+    }
+    pub fn remove_events_by_tag(&mut self, tag: &T) -> usize {
+        self.remove_events_where(|e| e.get_tag().as_ref() == Some(tag))
+    }
+    /// Cancels every pending event from `emitter`, returns how many were removed
+    pub fn cancel_events_from(&mut self, emitter: &EmRC<I>) -> usize {
+        #[cfg(test)]
+        println!("{} cancelled events from {:?}", self, emitter);
+
+        self.remove_events_where(|e| e.get_emitter() == *emitter)
+    }
+    /// Takes every pending event off the stack, in the order they would have been consumed
+    pub fn drain_stack(&mut self) -> impl Iterator<Item = Event<T, I>> + '_ {
+        self.journal(StackOp::Clear);
+        self.stack.drain(..).rev()
+    }
+    /// Returns how many pending events were cleared
+    pub fn clear_stack(&mut self) -> usize {
+        let len = self.stack.len();
+        self.journal(StackOp::Clear);
+        self.stack.clear();
+        len
+    }
+    /// The new listener is handed the retained events of sticky tags it is triggered by
     pub fn add_listener(&mut self, listener: LiRC<T, I>) -> Result<(), String> {
         if !self.has_listener(&listener) {
             #[cfg(test)]
             println!("{} added a listener: {:?}", self, listener.borrow());
 
-            self.listeners.push(listener);
+            self.index_listener(self.listeners.len(), &listener);
+            self.listeners.push(listener.clone());
+            self.replay_retained(&listener);
             Ok(())
         } else {
             Err(format!("EventHandler_{} already has {:?}", self, listener.borrow()))
         }
     }
+    /// Registers `emitter` so that it can be found from its id,
+    /// e.g. by events injected from other threads
+    pub fn register_emitter(&mut self, emitter: EmRC<I>) -> Result<(), String> {
+        if self.emitters.contains(&emitter) {
+            return Err(format!("{} already has {:?}", self, emitter.borrow()));
+        }
+        self.emitters.push(emitter);
+        Ok(())
+    }
+    pub fn unregister_emitter(&mut self, emitter: &EmRC<I>) -> bool {
+        let len = self.emitters.len();
+        self.emitters.retain(|e| e != emitter);
+        self.emitters.len() != len
+    }
+    pub fn get_emitters(&self) -> &Vec<EmRC<I>> {
+        &self.emitters
+    }
+    pub fn get_emitter_by_id(&self, emitter_id: &I) -> Option<EmRC<I>> {
+        self.emitters.iter().find(|e| e.borrow().get_id() == *emitter_id).cloned()
+    }
+    pub(crate) fn get_injection(&mut self) -> &Injection<T, I> {
+        self.injection.get_or_insert_with(Injection::new)
+    }
     pub fn get_listeners(&self) -> &Vec<LiRC<T, I>> {
         &self.listeners
     }
@@ -124,6 +555,59 @@ impl<T: Tag, I: Id> EventHandler<T, I> {
     pub fn has_listener(&self, listener: &LiRC<T, I>) -> bool {
         self.listeners.contains(listener)
     }
+    pub fn remove_listener(&mut self, listener: &LiRC<T, I>) -> Result<(), String> {
+        match self.listeners.iter().position(|l| l == listener) {
+            Some(pos) => {
+                #[cfg(test)]
+                println!("{} removed a listener: {:?}", self, listener.borrow());
+
+                self.listeners.remove(pos);
+                self.reindex_listeners();
+                self.remove_mailbox(listener);
+                Ok(())
+            }
+            None => Err(format!("{} does not have {:?}", self, listener.borrow())),
+        }
+    }
+    pub fn get_dispatch_mode(&self) -> DispatchMode {
+        self.dispatch_mode
+    }
+    pub fn set_dispatch_mode(&mut self, mode: DispatchMode) {
+        self.dispatch_mode = mode;
+    }
+    fn index_listener(&mut self, pos: usize, listener: &LiRC<T, I>) {
+        for t in listener.borrow().get_triggers() {
+            let positions = self.index.entry(std::mem::discriminant(t)).or_default();
+            if positions.last() != Some(&pos) {
+                positions.push(pos);
+            }
+        }
+    }
+    /// Rebuilds the tag index from the current triggers of every listener
+    pub fn reindex_listeners(&mut self) {
+        self.index.clear();
+        self.index_generation = TRIGGERS_GENERATION.load(std::sync::atomic::Ordering::SeqCst);
+        for (pos, li) in self.listeners.clone().iter().enumerate() {
+            self.index_listener(pos, li);
+        }
+    }
+    /// Whether no listener's triggers changed since the index was built
+    fn is_index_current(&self) -> bool {
+        self.index_generation == TRIGGERS_GENERATION.load(std::sync::atomic::Ordering::SeqCst)
+    }
+    /// Listeners whose triggers include `tag`, in the order they were added
+    pub fn get_triggered_listeners(&self, tag: &T) -> Vec<&LiRC<T, I>> {
+        if self.dispatch_mode == DispatchMode::Linear || !self.is_index_current() {
+            return self.listeners.iter().filter(|li| li.borrow().has_trigger(tag)).collect();
+        }
+        match self.index.get(&std::mem::discriminant(tag)) {
+            Some(positions) => positions.iter()
+                .map(|&pos| &self.listeners[pos])
+                .filter(|li| li.borrow().has_trigger(tag))
+                .collect(),
+            None => vec![],
+        }
+    }
     pub fn peek_next(&self) -> Option<&Event<T, I>> {
         #[cfg(test)]
         {
@@ -142,13 +626,16 @@ impl<T: Tag, I: Id> EventHandler<T, I> {
         }
     }
     pub fn peek_next_emitter(&self) -> Option<EmRC<I>> {
-        if let Some(e) = self.peek_next() {
-            Some(e.get_emitter())
-        } else {
-            None
-        }
+        self.peek_next().map(|e| e.get_emitter())
     }
     pub fn pop_next(&mut self) -> Option<Event<T, I>> {
+        let next = self.take_next();
+        if let Some(e) = &next {
+            self.journal(StackOp::Remove(e.get_id()));
+        }
+        next
+    }
+    fn take_next(&mut self) -> Option<Event<T, I>> {
         if let Some(ret) = self.stack.pop() {
             self.prev_event = Some(ret.clone());
 
@@ -172,27 +659,502 @@ impl<T: Tag, I: Id> EventHandler<T, I> {
 
         self.push_event(Some(Event::new(emitter.clone(), Some(tag))));
     }
+    /// Like `emit`, but fails with the event if the stack is full and the overflow policy rejects it
+    pub fn try_emit(&mut self, emitter: EmRC<I>, tag: T) -> Result<(), StackFull<T, I>> {
+        #[cfg(test)]
+        println!("{} tried to emit {:?} from {:?}", self, tag, emitter);
+
+        self.try_push_event(Event::new(emitter, Some(tag)))
+    }
     pub fn consume_next_event(&mut self) {
-        if let Some(next) = self.pop_next() {        
+        // The event only leaves the journal once broadcast, so it is replayed if dispatching never ends
+        if let Some(next) = self.take_next() {        
             #[cfg(test)]
             println!("{} consumed {:?}", self, next);
 
+            let id = next.get_id();
             self.broadcast_event(next);
+            self.journal(StackOp::Remove(id));
         }
     }
     pub fn broadcast_event(&mut self, event: Event<T, I>) {
         #[cfg(test)]
         println!("{} broadcast {:?}", self, event);
 
-        for li in self.get_listeners() {
-            if li.borrow().get_triggers().contains(&&event.get_tag().expect("Untagged")) {
-                li.borrow().on_triggers(vec![event.clone()]);
+        if self.dispatch_mode == DispatchMode::Indexed && !self.is_index_current() {
+            self.reindex_listeners();
+        }
+        let outer = self.dispatching.replace((event.get_id(), event.get_depth()));
+        // Listeners cannot reach the handler while it is dispatching, so the mailboxes can be taken out
+        let mut actors = self.actors.take();
+
+        let dead = if let Some(addressee) = event.get_reply_to() {
+            // Replies only go back to the query's emitter, whatever its triggers
+            actor::deliver(&mut actors, self.get_listeners().iter().filter(|li| **li == addressee), &event)
+        } else if let Some(tag) = event.get_tag() {
+            if event.is_query() {
+                let replies = self.collect_replies(&event, false);
+                for (li, reply) in replies.iter() {
+                    let reply = Event::new_reply(li.borrow().as_emrc(), *reply, &event);
+                    self.push_event(Some(reply));
+                }
+                let unheard = replies.is_empty() && self.get_triggered_listeners(&tag).is_empty();
+                unheard.then(|| DeadLetter { event: event.clone(), reason: DeadLetterReason::NoListeners, errors: vec![] })
+            } else {
+                actor::deliver(&mut actors, self.get_triggered_listeners(&tag), &event)
+            }
+        } else {
+            Some(DeadLetter { event: event.clone(), reason: DeadLetterReason::Untagged, errors: vec![] })
+        };
+        self.actors = actors;
+        let dead = match (dead, self.dispatch_parallel(&event)) {
+            // Parallel listeners cannot reply, but they heard the query
+            (Some(_), Some(_)) if event.is_query() => None,
+            (Some(_), Some(Ok(()))) => None,
+            (Some(mut letter), Some(Err(errors))) => {
+                letter.reason = DeadLetterReason::AllFailed;
+                letter.errors.extend(errors);
+                Some(letter)
+            }
+            (dead, _) => dead,
+        };
+        self.retained.record(&event);
+        self.remove_dropped_observers();
+        // Observers can drop handles, so the queued removals are checked before each of them
+        for (id, observer) in self.observers.clone() {
+            if !self.observer_removals.borrow().contains(&id) {
+                observer(&event);
             }
         }
+        self.remove_dropped_observers();
+        if let Some(letter) = dead {
+            self.bury(letter);
+        }
+
+        self.drain_inbox();
+        self.dispatching = outer;
+    }
+    /// Calls `observer` with every event broadcast from now on, whatever its tag.
+    /// Observers run while the handler is borrowed, so they should push events through an `Inbox`.
+    /// Returns an id for `remove_observer`.
+    pub fn add_observer(&mut self, observer: impl Fn(&Event<T, I>) + 'static) -> usize {
+        let id = IDCOUNTER.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        self.observers.push((id, Rc::new(observer)));
+        id
+    }
+    /// Like `add_observer`, but the observer is removed once the returned handle is dropped
+    pub fn observe(eh: &EHRc<T, I>, observer: impl Fn(&Event<T, I>) + 'static) -> ObserverHandle<T, I> {
+        let mut handler = eh.borrow_mut();
+        let id = handler.add_observer(observer);
+        ObserverHandle { id, handler: Rc::downgrade(eh), removals: Rc::downgrade(&handler.observer_removals) }
+    }
+    pub fn remove_observer(&mut self, observer_id: usize) -> bool {
+        let len = self.observers.len();
+        self.observers.retain(|(id, _)| *id != observer_id);
+        self.observers.len() != len
+    }
+    fn remove_dropped_observers(&mut self) {
+        let removals = std::mem::take(&mut *self.observer_removals.borrow_mut());
+        self.observers.retain(|(id, _)| !removals.contains(id));
+    }
+    /// Observers still running, those whose handles were dropped excluded
+    pub fn get_observer_count(&self) -> usize {
+        let removals = self.observer_removals.borrow();
+        self.observers.iter().filter(|(id, _)| !removals.contains(id)).count()
+    }
+    pub(crate) fn bury(&mut self, letter: DeadLetter<T, I>) {
+        #[cfg(test)]
+        println!("{} got a dead letter: {:?}", self, letter);
+
+        self.dead_letters.bury(letter);
+    }
+    /// Runs `f` as if `event` was being broadcast, so events pushed meanwhile are caused by it
+    pub(crate) fn with_dispatching<R>(&mut self, event: &Event<T, I>, f: impl FnOnce(&mut Self) -> R) -> R {
+        let outer = self.dispatching.replace((event.get_id(), event.get_depth()));
+        let ret = f(self);
+        self.dispatching = outer;
+        ret
+    }
+    /// Whether a `ParallelDispatcher` hands the broadcast events to its listeners
+    pub fn is_parallel_mode(&self) -> bool {
+        self.parallel.as_ref().is_some_and(|p| p.strong_count() > 0)
+    }
+    pub(crate) fn set_parallel_dispatch(&mut self, parallel: Option<Weak<RefCell<dyn ParallelDispatch<T, I>>>>) {
+        self.parallel = parallel;
+    }
+    /// Hands `event` to the parallel listeners, see `ParallelDispatch::dispatch`
+    fn dispatch_parallel(&mut self, event: &Event<T, I>) -> Option<Result<(), Vec<String>>> {
+        let Some(parallel) = self.parallel.as_ref()?.upgrade() else {
+            self.parallel = None;
+            return None;
+        };
+        parallel.borrow_mut().dispatch(event)
+    }
+    pub(crate) fn get_actors(&self) -> &Option<Actors<T, I>> {
+        &self.actors
+    }
+    pub(crate) fn get_actors_mut(&mut self) -> &mut Option<Actors<T, I>> {
+        &mut self.actors
+    }
+    pub fn get_dead_letter_sink(&self) -> &DeadLetterSink<T, I> {
+        self.dead_letters.get_sink()
+    }
+    pub fn set_dead_letter_sink(&mut self, sink: DeadLetterSink<T, I>) {
+        self.dead_letters.set_sink(sink);
+    }
+    /// Dead letters kept by `DeadLetterSink::Collect`
+    pub fn get_dead_letters(&self) -> &Vec<DeadLetter<T, I>> {
+        self.dead_letters.get_collected()
+    }
+    pub fn take_dead_letters(&mut self) -> Vec<DeadLetter<T, I>> {
+        self.dead_letters.take_collected()
+    }
+    /// Number of dead letters so far, whatever the sink
+    pub fn get_dead_letter_count(&self) -> usize {
+        self.dead_letters.get_count()
     }
     pub fn broadcast_events(&mut self, events: Vec<Event<T, I>>) {
         for e in events {
             self.broadcast_event(e);
         }
     }
-}
\ No newline at end of file
+    fn collect_replies(&self, query: &Event<T, I>, first_only: bool) -> Vec<(LiRC<T, I>, T)> {
+        let mut replies = vec![];
+        let Some(tag) = query.get_tag() else { return replies };
+
+        for li in self.get_triggered_listeners(&tag) {
+            if let Some(reply) = li.borrow().on_query(query) {
+                replies.push((li.clone(), reply));
+                if first_only {
+                    break;
+                }
+            }
+        }
+        replies
+    }
+    fn ask(&mut self, emitter: EmRC<I>, tag: T, first_only: bool) -> Vec<T> {
+        let query = Event::new_query(emitter, tag, IDCOUNTER.fetch_add(1, std::sync::atomic::Ordering::SeqCst));
+        #[cfg(test)]
+        println!("{} queried {:?}", self, query);
+
+        let replies = self.collect_replies(&query, first_only);
+        self.drain_inbox();
+        replies.into_iter().map(|(_, reply)| reply).collect()
+    }
+    /// Asks every listener triggered by `tag` and returns all of their replies
+    pub fn query_all(&mut self, emitter: EmRC<I>, tag: T) -> Vec<T> {
+        self.ask(emitter, tag, false)
+    }
+    /// Asks listeners triggered by `tag` until one of them replies
+    pub fn query_first(&mut self, emitter: EmRC<I>, tag: T) -> Option<T> {
+        self.ask(emitter, tag, true).pop()
+    }
+    pub fn query_fold<A>(&mut self, emitter: EmRC<I>, tag: T, init: A, f: impl FnMut(A, T) -> A) -> A {
+        self.ask(emitter, tag, false).into_iter().fold(init, f)
+    }
+    /// Pushes a query onto the stack. Once it is consumed, the replies are
+    /// pushed back as reply events addressed to `emitter`.
+    /// Returns the correlation id of the query.
+    ///
+    /// Replies only reach listeners, so this fails if `emitter` is not one of the handler's
+    /// listeners. Other emitters can use `query_all` and the like.
+    pub fn push_query(&mut self, emitter: EmRC<I>, tag: T) -> Result<usize, String> {
+        if !self.listeners.iter().any(|li| *li == emitter) {
+            return Err(format!("{} cannot route replies to {:?}, which is not one of its listeners", self, emitter.borrow()));
+        }
+        let correlation = IDCOUNTER.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        self.push_event(Some(Event::new_query(emitter, tag, correlation)));
+        Ok(correlation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{def_emitter::DefEmitter as DEm, tests::{TestTags::{self, *}, Recorder}};
+    use super::*;
+
+    /// Handler with a window listener asking questions and three listeners
+    /// answering T1 queries with T2, T3 and nothing
+    fn answering_handler() -> (EHRc<TestTags, usize>, Recorder) {
+        let eh = EventHandler::new_ehrc();
+        let window = Recorder::new(vec![T2], None);
+        for li in [window.clone(), Recorder::new(vec![T1], Some(T2)), Recorder::new(vec![T1], Some(T3)), Recorder::new(vec![T1], None)] {
+            eh.borrow_mut().add_listener(li.as_lirc()).unwrap();
+        }
+        (eh, window)
+    }
+
+    #[test]
+    fn direct_queries() {
+        let (eh, window) = answering_handler();
+
+        assert_eq!(eh.borrow_mut().query_all(window.as_emrc(), T1), vec![T2, T3]);
+        assert_eq!(eh.borrow_mut().query_first(window.as_emrc(), T1), Some(T2));
+        assert_eq!(eh.borrow_mut().query_first(window.as_emrc(), T2), None);
+        let can_close = eh.borrow_mut().query_fold(window.as_emrc(), T1, true, |ok, r| ok && r != T3);
+        assert!(!can_close);
+        assert_eq!(eh.borrow().get_stack_len(), 0);
+    }
+
+    #[test]
+    fn pushed_query_replies_reach_the_emitter() {
+        let (eh, window) = answering_handler();
+
+        let correlation = eh.borrow_mut().push_query(window.as_emrc(), T1).unwrap();
+        eh.borrow_mut().consume_next_event();
+        assert_eq!(eh.borrow().get_stack_len(), 2);
+        assert!(eh.borrow().get_stack().iter().all(|e| e.is_reply() && e.get_correlation_id() == Some(correlation)));
+
+        eh.borrow_mut().consume_next_event();
+        eh.borrow_mut().consume_next_event();
+        assert_eq!(window.received_tags(), vec![Some(T3), Some(T2)]);
+    }
+
+    #[test]
+    fn pushed_query_needs_a_listening_emitter() {
+        let (eh, _) = answering_handler();
+        let em = DEm::<TestTags>::new_emrc(None);
+
+        assert!(eh.borrow_mut().push_query(em, T1).is_err());
+        assert_eq!(eh.borrow().get_stack_len(), 0);
+    }
+
+    /// Handler in `mode` with listeners on T1 and T4(1), on T4(2) and T1, and on T2,
+    /// with T4(2), T3 and T1 pending
+    fn dispatching_handler(mode: DispatchMode) -> (EHRc<TestTags, usize>, [Recorder; 3]) {
+        let eh = EventHandler::new_ehrc();
+        let em = DEm::<TestTags>::new_emrc(None);
+        let listeners = [Recorder::new(vec![T1, T4(1)], None), Recorder::new(vec![T4(2), T1], None), Recorder::new(vec![T2], None)];
+        eh.borrow_mut().set_dispatch_mode(mode);
+        for li in &listeners {
+            eh.borrow_mut().add_listener(li.as_lirc()).unwrap();
+        }
+        eh.borrow_mut().push_events(Some(vec![
+            Event::new(em.clone(), Some(T4(2))),
+            Event::new(em.clone(), Some(T3)),
+            Event::new(em.clone(), Some(T1)),
+        ]));
+        (eh, listeners)
+    }
+
+    fn consume_all(eh: &EHRc<TestTags, usize>) {
+        while eh.borrow().get_stack_len() > 0 {
+            eh.borrow_mut().consume_next_event();
+        }
+    }
+
+    #[test]
+    fn dispatch_modes_reach_the_same_listeners() {
+        for mode in [DispatchMode::Linear, DispatchMode::Indexed] {
+            let (eh, [li1, li2, li3]) = dispatching_handler(mode);
+
+            eh.borrow_mut().remove_listener(&li3.as_lirc()).unwrap();
+            assert!(eh.borrow_mut().remove_listener(&li3.as_lirc()).is_err());
+            assert_eq!(eh.borrow().get_triggered_listeners(&T1), vec![&li1.as_lirc(), &li2.as_lirc()]);
+            consume_all(&eh);
+
+            assert_eq!(li1.received_tags(), vec![Some(T1)]);
+            assert_eq!(li2.received_tags(), vec![Some(T1), Some(T4(2))]);
+            assert_eq!(li3.received_tags(), vec![]);
+        }
+    }
+
+    #[test]
+    fn indexed_dispatch_follows_changed_triggers() {
+        use crate::state_machine::{StateMachine, InvalidPolicy};
+
+        let (eh, [li1, ..]) = dispatching_handler(DispatchMode::Indexed);
+        let sm = StateMachine::new((), InvalidPolicy::Ignore).register(&eh).unwrap();
+
+        // Registered with no trigger, the machine only reacts to T3 once the index is rebuilt
+        sm.borrow_mut().add_transition((), T3, (), Some(T4(1)));
+        assert_eq!(eh.borrow().get_triggered_listeners(&T3).len(), 1);
+        consume_all(&eh);
+
+        assert_eq!(li1.received_tags(), vec![Some(T1), Some(T4(1))]);
+    }
+
+    /// Handler with T1 to T4(2) pending from two emitters, T4(2) on top
+    fn pending_handler() -> (EHRc<TestTags, usize>, EmRC<usize>, EmRC<usize>) {
+        let eh = EventHandler::new_ehrc();
+        let em1 = DEm::<TestTags>::new_emrc(None);
+        let em2 = DEm::<TestTags>::new_emrc(None);
+        eh.borrow_mut().push_events(Some(vec![
+            Event::new(em1.clone(), Some(T1)),
+            Event::new(em2.clone(), Some(T2)),
+            Event::new(em1.clone(), Some(T3)),
+            Event::new(em2.clone(), Some(T4(1))),
+            Event::new(em1.clone(), Some(T4(2))),
+        ]));
+        (eh, em1, em2)
+    }
+
+    #[test]
+    fn peek_and_iterate_in_consumption_order() {
+        let (eh, _, _) = pending_handler();
+
+        assert_eq!(eh.borrow().peek_nth(0), eh.borrow().peek_next());
+        assert_eq!(eh.borrow().peek_nth(1).unwrap().get_tag(), Some(T4(1)));
+        assert_eq!(eh.borrow().peek_nth(5), None);
+        assert_eq!(eh.borrow().iter_stack().map(|e| e.get_tag().unwrap()).collect::<Vec<_>>(), vec![T4(2), T4(1), T3, T2, T1]);
+    }
+
+    #[test]
+    fn remove_pending_events() {
+        let (eh, _, em2) = pending_handler();
+
+        assert_eq!(eh.borrow_mut().cancel_events_from(&em2), 2);
+        assert!(!eh.borrow().stack_has_emitter(&em2));
+        assert_eq!(eh.borrow_mut().remove_events_by_tag(&T3), 1);
+        assert_eq!(eh.borrow_mut().remove_events_where(|e| matches!(e.get_tag(), Some(T4(_)))), 1);
+        assert_eq!(eh.borrow().get_stack_tags(), vec![Some(T1)]);
+        assert_eq!(eh.borrow_mut().clear_stack(), 1);
+    }
+
+    #[test]
+    fn push_front_retain_and_drain() {
+        let (eh, _, em2) = pending_handler();
+
+        eh.borrow_mut().push_event_front(Event::new(em2.clone(), Some(T5("last"))));
+        eh.borrow_mut().emit(em2.clone(), T2);
+        assert_eq!(eh.borrow().iter_stack().last().unwrap().get_tag(), Some(T5("last")));
+        assert_eq!(eh.borrow_mut().retain_events(|e| e.get_emitter() == em2), 3);
+        let drained = eh.borrow_mut().drain_stack().map(|e| e.get_tag()).collect::<Vec<_>>();
+        assert_eq!(drained, vec![Some(T2), Some(T4(1)), Some(T2), Some(T5("last"))]);
+        assert_eq!(eh.borrow().get_stack_len(), 0);
+    }
+
+    fn sum_t4s(pending: &Event<TestTags, usize>, new: &Event<TestTags, usize>) -> Option<Event<TestTags, usize>> {
+        match (pending.get_tag(), new.get_tag()) {
+            (Some(T4(a)), Some(T4(b))) => Some(Event::new(new.get_emitter(), Some(T4(a + b)))),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn coalesce_duplicates() {
+        let eh = EventHandler::<TestTags, usize>::new_ehrc();
+        let sensor = DEm::<TestTags>::new_emrc(None);
+        let other = DEm::<TestTags>::new_emrc(None);
+
+        eh.borrow_mut().set_coalesce_policy(CoalescePolicy::DropDuplicates);
+        for _ in 0..10 {
+            eh.borrow_mut().emit(sensor.clone(), T1);
+        }
+        eh.borrow_mut().emit(sensor.clone(), T2);
+        eh.borrow_mut().emit(other.clone(), T1);
+        assert_eq!(eh.borrow().get_stack_tags(), vec![Some(T1), Some(T2), Some(T1)]);
+        assert_eq!(eh.borrow().get_coalesced_count(), 9);
+    }
+
+    #[test]
+    fn coalesce_latest_per_emitter() {
+        let eh = EventHandler::<TestTags, usize>::new_ehrc();
+        let sensor = DEm::<TestTags>::new_emrc(None);
+        let other = DEm::<TestTags>::new_emrc(None);
+
+        eh.borrow_mut().set_coalesce_policy(CoalescePolicy::LatestPerEmitter);
+        for i in 0..10 {
+            eh.borrow_mut().emit(sensor.clone(), T4(i));
+        }
+        eh.borrow_mut().emit(other.clone(), T2);
+        assert_eq!(eh.borrow().get_stack_tags(), vec![Some(T4(9)), Some(T2)]);
+        assert_eq!(eh.borrow().get_coalesced_count(), 9);
+    }
+
+    #[test]
+    fn coalesce_by_merging() {
+        let eh = EventHandler::<TestTags, usize>::new_ehrc();
+        let sensor = DEm::<TestTags>::new_emrc(None);
+
+        eh.borrow_mut().set_coalesce_policy(CoalescePolicy::Merge(Rc::new(sum_t4s)));
+        for i in 1..=4 {
+            eh.borrow_mut().emit(sensor.clone(), T4(i));
+        }
+        eh.borrow_mut().emit(sensor.clone(), T3);
+        assert_eq!(eh.borrow().get_stack_tags(), vec![Some(T4(10)), Some(T3)]);
+        assert_eq!(eh.borrow().get_coalesced_count(), 3);
+    }
+
+    #[test]
+    fn merged_events_take_the_pushed_headers() {
+        let eh = EventHandler::<TestTags, usize>::new_ehrc();
+        let em = DEm::<TestTags>::new_emrc(None);
+        eh.borrow_mut().set_coalesce_policy(CoalescePolicy::Merge(Rc::new(|_, new: &Event<TestTags, usize>| {
+            Some(Event::new(new.get_emitter(), Some(T3)).with_header("merged", "yes"))
+        })));
+        eh.borrow_mut().emit(em.clone(), T1);
+        eh.borrow_mut().push_event(Some(Event::new(em.clone(), Some(T2)).with_header("user", "ob").with_header("merged", "no")));
+
+        let merged = eh.borrow().peek_next().unwrap().clone();
+        assert_eq!(merged.get_headers().collect::<Vec<_>>(), vec![("merged", "yes"), ("user", "ob")]);
+        assert_eq!(merged.get_handler_id(), Some(eh.borrow().get_id()));
+    }
+
+    #[test]
+    fn pushing_to_the_front_coalesces() {
+        let eh = EventHandler::<TestTags, usize>::new_ehrc();
+        let sensor = DEm::<TestTags>::new_emrc(None);
+
+        eh.borrow_mut().set_coalesce_policy(CoalescePolicy::DropDuplicates);
+        eh.borrow_mut().emit(sensor.clone(), T1);
+        eh.borrow_mut().push_event_front(Event::new(sensor.clone(), Some(T1)));
+        eh.borrow_mut().push_event_front(Event::new(sensor.clone(), Some(T2)));
+        assert_eq!(eh.borrow().get_stack_tags(), vec![Some(T2), Some(T1)]);
+        assert_eq!(eh.borrow().get_coalesced_count(), 1);
+    }
+
+    #[test]
+    fn observer_handles() {
+        let eh = EventHandler::<TestTags, usize>::new_ehrc();
+        let em = DEm::<TestTags>::new_emrc(None);
+        let seen = Rc::new(RefCell::new(vec![]));
+
+        let seen2 = seen.clone();
+        let kept = EventHandler::observe(&eh, move |e| seen2.borrow_mut().push(("kept", e.get_tag())));
+        // Drops the next handle mid-broadcast, so while the handler is borrowed
+        let dropped: Rc<RefCell<Option<ObserverHandle<TestTags, usize>>>> = Rc::new(RefCell::new(None));
+        let dropped2 = dropped.clone();
+        eh.borrow_mut().add_observer(move |e| if e.get_tag() == Some(T2) {
+            dropped2.borrow_mut().take();
+        });
+        let seen2 = seen.clone();
+        *dropped.borrow_mut() = Some(EventHandler::observe(&eh, move |e| seen2.borrow_mut().push(("dropped", e.get_tag()))));
+        assert_eq!(eh.borrow().get_observer_count(), 3);
+
+        for tag in [T1, T2, T3] {
+            eh.borrow_mut().emit(em.clone(), tag);
+            eh.borrow_mut().consume_next_event();
+        }
+        assert_eq!(eh.borrow().get_observer_count(), 2);
+        assert_eq!(*seen.borrow(), vec![("kept", Some(T1)), ("dropped", Some(T1)), ("kept", Some(T2)), ("kept", Some(T3))]);
+
+        drop(kept);
+        assert_eq!(eh.borrow().get_observer_count(), 1);
+    }
+
+    #[test]
+    fn clones_get_their_own_id_and_inbox() {
+        let eh = EventHandler::<TestTags, usize>::new_ehrc();
+        let em = DEm::<TestTags>::new_emrc(None);
+        let on_t1 = Recorder::new(vec![T1], None);
+        eh.borrow_mut().add_listener(on_t1.as_lirc()).unwrap();
+        eh.borrow_mut().add_observer(|_| {});
+        eh.borrow_mut().emit(em.clone(), T1);
+
+        let clone = eh.borrow().as_ehrc();
+        assert_ne!(clone.borrow().get_id(), eh.borrow().get_id());
+        assert_eq!(clone.borrow().get_stack_tags(), vec![Some(T1)]);
+        assert_eq!(clone.borrow().get_observer_count(), 0);
+
+        // Events sent to the original while it is busy stay with it
+        let inbox = Inbox::new(&eh);
+        let _busy = eh.borrow_mut();
+        inbox.emit(em.clone(), T2);
+        clone.borrow_mut().drain_inbox();
+        assert_eq!(clone.borrow().get_stack_tags(), vec![Some(T1)]);
+
+        clone.borrow_mut().consume_next_event();
+        assert_eq!(on_t1.received_tags(), vec![Some(T1)]);
+    }
+}
@@ -0,0 +1,332 @@
+use std::{any::Any, cell::Cell, collections::VecDeque};
+use crate::{prelude::*, event::Event, event_handler::EventHandler, IDCOUNTER};
+
+type Subscriber<V> = Box<dyn FnMut(V)>;
+
+/// Subscribers of a flow, along with the values sent while they run
+struct Subscribers<V> {
+    list: RefCell<Vec<Subscriber<V>>>,
+    /// Values waiting for the subscribers, sent in order once the running ones return
+    queued: RefCell<VecDeque<V>>,
+    sending: Cell<bool>,
+}
+
+/// Stream of values derived from the events consumed by an `EventHandler`.
+///
+/// Flows start from a handler, are transformed by combinators, each giving a
+/// new flow, and end up pushed back into a handler as events. Values are pushed
+/// through as soon as the source handler broadcasts an event, and derived events
+/// go through an `Inbox`, so flows can feed the handler they come from.
+/// Values sent into a flow while its subscribers run, and subscribers added
+/// meanwhile, are held back until the current value went through.
+/// Flows keep the flows they derive from alive, and the observer a flow adds to
+/// its handler is removed once that flow and every flow derived from it are dropped.
+pub struct Flow<V: 'static> {
+    subscribers: Rc<Subscribers<V>>,
+    /// What feeds the flow, the flows it derives from or the `ObserverHandle` of its handler
+    upstream: Vec<Rc<dyn Any>>,
+}
+
+impl<V: 'static> Clone for Flow<V> {
+    fn clone(&self) -> Self {
+        Self { subscribers: self.subscribers.clone(), upstream: self.upstream.clone() }
+    }
+}
+
+impl<V: 'static> Debug for Flow<V> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Flow")
+            .field("subscribers", &self.subscribers.list.borrow().len())
+            .field("queued", &self.subscribers.queued.borrow().len())
+            .finish()
+    }
+}
+
+impl<V: Clone + 'static> Flow<V> {
+    fn new(upstream: Vec<Rc<dyn Any>>) -> Self {
+        let subscribers = Subscribers { list: RefCell::new(Vec::new()), queued: RefCell::new(VecDeque::new()), sending: Cell::new(false) };
+        Self { subscribers: Rc::new(subscribers), upstream }
+    }
+    /// The flow without its upstream, for the subscribers of upstream flows to send through
+    fn sender(&self) -> Self {
+        Self { subscribers: self.subscribers.clone(), upstream: vec![] }
+    }
+    fn send(&self, value: V) {
+        let subscribers = &self.subscribers;
+        subscribers.queued.borrow_mut().push_back(value);
+        if subscribers.sending.replace(true) {
+            return;
+        }
+        loop {
+            let next = subscribers.queued.borrow_mut().pop_front();
+            let Some(value) = next else { break };
+            // Subscribers run out of the list, so that they can subscribe and send into this flow
+            let mut running = std::mem::take(&mut *subscribers.list.borrow_mut());
+            for subscriber in running.iter_mut() {
+                subscriber(value.clone());
+            }
+            let mut list = subscribers.list.borrow_mut();
+            let added = std::mem::replace(&mut *list, running);
+            list.extend(added);
+        }
+        subscribers.sending.set(false);
+    }
+    /// New flow, fed by `f` with every value of this one
+    fn derive<W: Clone + 'static>(&self, mut f: impl FnMut(V, &Flow<W>) + 'static) -> Flow<W> {
+        let derived = Flow::new(vec![Rc::new(self.clone())]);
+        let out = derived.sender();
+        self.for_each(move |v| f(v, &out));
+        derived
+    }
+    /// Values `select` picks from the events broadcast by `eh`
+    pub fn from_handler<T: Tag, I: Id>(eh: &EHRc<T, I>, select: impl Fn(&Event<T, I>) -> Option<V> + 'static) -> Self {
+        let flow = Flow::new(vec![]);
+        let source = Rc::downgrade(&flow.subscribers);
+        let observer = EventHandler::observe(eh, move |e| {
+            if let Some(subscribers) = source.upgrade() && let Some(v) = select(e) {
+                Flow { subscribers, upstream: vec![] }.send(v);
+            }
+        });
+        Flow { upstream: vec![Rc::new(observer)], ..flow }
+    }
+    /// Calls `f` with every value
+    pub fn for_each(&self, f: impl FnMut(V) + 'static) {
+        self.subscribers.list.borrow_mut().push(Box::new(f));
+    }
+    pub fn map<W: Clone + 'static>(&self, f: impl Fn(V) -> W + 'static) -> Flow<W> {
+        self.derive(move |v, out| out.send(f(v)))
+    }
+    pub fn filter(&self, keep: impl Fn(&V) -> bool + 'static) -> Flow<V> {
+        self.derive(move |v, out| if keep(&v) { out.send(v) })
+    }
+    /// Running fold of the values, sending every intermediate result
+    pub fn scan<A: Clone + 'static>(&self, init: A, f: impl Fn(A, V) -> A + 'static) -> Flow<A> {
+        let mut acc = Some(init);
+        self.derive(move |v, out| {
+            let next = f(acc.take().unwrap(), v);
+            acc = Some(next.clone());
+            out.send(next);
+        })
+    }
+    /// Only the first `n` values
+    pub fn take(&self, n: usize) -> Flow<V> {
+        let mut left = n;
+        self.derive(move |v, out| if left > 0 {
+            left -= 1;
+            out.send(v);
+        })
+    }
+    /// Every value but the first `n`
+    pub fn skip(&self, n: usize) -> Flow<V> {
+        let mut left = n;
+        self.derive(move |v, out| match left {
+            0 => out.send(v),
+            _ => left -= 1,
+        })
+    }
+    /// Values along with the values of `other`, both flows sending them in the same order
+    pub fn merge(&self, other: &Flow<V>) -> Flow<V> {
+        let merged = Flow::new(vec![Rc::new(self.clone()), Rc::new(other.clone())]);
+        let out = merged.sender();
+        self.for_each(move |v| out.send(v));
+        let out = merged.sender();
+        other.for_each(move |v| out.send(v));
+        merged
+    }
+    /// Pairs the n-th values of both flows, holding on to values until the other flow catches up
+    pub fn zip<W: Clone + 'static>(&self, other: &Flow<W>) -> Flow<(V, W)> {
+        let pending: Rc<RefCell<(VecDeque<V>, VecDeque<W>)>> = Rc::new(RefCell::new((VecDeque::new(), VecDeque::new())));
+        let zipped = Flow::new(vec![Rc::new(self.clone()), Rc::new(other.clone())]);
+
+        let (queues, out) = (pending.clone(), zipped.sender());
+        self.for_each(move |v| {
+            let w = queues.borrow_mut().1.pop_front();
+            match w {
+                Some(w) => out.send((v, w)),
+                None => queues.borrow_mut().0.push_back(v),
+            }
+        });
+        let out = zipped.sender();
+        other.for_each(move |w| {
+            let v = pending.borrow_mut().0.pop_front();
+            match v {
+                Some(v) => out.send((v, w)),
+                None => pending.borrow_mut().1.push_back(w),
+            }
+        });
+        zipped
+    }
+    /// Groups values `n` at a time, sending each group once it is full
+    pub fn buffer(&self, n: usize) -> Flow<Vec<V>> {
+        let mut group = Vec::with_capacity(n);
+        self.derive(move |v, out| {
+            group.push(v);
+            if group.len() >= n {
+                out.send(std::mem::replace(&mut group, Vec::with_capacity(n)));
+            }
+        })
+    }
+}
+
+impl<V: Clone + PartialEq + 'static> Flow<V> {
+    /// Drops values equal to the one sent right before them
+    pub fn distinct_until_changed(&self) -> Flow<V> {
+        let mut last: Option<V> = None;
+        self.derive(move |v, out| if last.as_ref() != Some(&v) {
+            last = Some(v.clone());
+            out.send(v);
+        })
+    }
+}
+
+impl<T: Tag> Flow<T> {
+    /// Tags of the events broadcast by `eh`
+    pub fn tags<I: Id>(eh: &EHRc<T, I>) -> Self {
+        Self::from_handler(eh, |e| e.get_tag())
+    }
+    /// Pushes every tag into `eh` as an event from `emitter`
+    pub fn emit_into<I: Id>(&self, eh: &EHRc<T, I>, emitter: EmRC<I>) {
+        let inbox = Inbox::new(eh);
+        self.for_each(move |tag| inbox.emit(emitter.clone(), tag));
+    }
+    /// Pushes every tag into `eh` as an event from a new emitter, which is returned
+    pub fn into_emitter(&self, eh: &EHRc<T, usize>) -> EmRC<usize> {
+        let emitter = EmRC(Rc::new(RefCell::new(FlowEmitter { id: IDCOUNTER.fetch_add(1, std::sync::atomic::Ordering::SeqCst) })));
+        self.emit_into(eh, emitter.clone());
+        emitter
+    }
+}
+
+/// Emitter of the events a `Flow` pushes into a handler
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlowEmitter {
+    id: usize,
+}
+
+impl EmitObj<usize> for FlowEmitter {
+    fn get_id(&self) -> usize {
+        self.id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{def_emitter::DefEmitter as DEm, tests::TestTags::{self, *}};
+    use super::*;
+
+    type EH = EventHandler<TestTags, usize>;
+
+    fn collect<V: Clone + 'static>(flow: &Flow<V>) -> Rc<RefCell<Vec<V>>> {
+        let values = Rc::new(RefCell::new(vec![]));
+        let sink = values.clone();
+        flow.for_each(move |v| sink.borrow_mut().push(v));
+        values
+    }
+
+    fn numbers(eh: &EHRc<TestTags, usize>) -> Flow<i32> {
+        Flow::from_handler(eh, |e| match e.get_tag() {
+            Some(T4(n)) => Some(n),
+            _ => None,
+        })
+    }
+
+    fn feed(eh: &EHRc<TestTags, usize>, tags: impl IntoIterator<Item = TestTags>) {
+        let em = DEm::<TestTags>::new_emrc(None);
+        for tag in tags {
+            eh.borrow_mut().emit(em.clone(), tag);
+            eh.borrow_mut().consume_next_event();
+        }
+    }
+
+    #[test]
+    fn combinators() {
+        let eh = EH::new_ehrc();
+        let nums = numbers(&eh);
+        let big = nums.map(|n| n * 2).filter(|n| *n > 2);
+        let all = [
+            collect(&nums),
+            collect(&big),
+            collect(&nums.scan(0, |sum, n| sum + n)),
+            collect(&nums.take(2)),
+            collect(&nums.skip(1)),
+            collect(&nums.distinct_until_changed()),
+            collect(&nums.merge(&big)),
+        ];
+        let pairs = collect(&nums.zip(&nums.skip(1)));
+        let groups = collect(&nums.buffer(2));
+
+        feed(&eh, [T4(1), T4(1), T4(2), T4(3), T2]);
+        let expected: [Vec<i32>; 7] = [
+            vec![1, 1, 2, 3],
+            vec![4, 6],
+            vec![1, 2, 4, 7],
+            vec![1, 1],
+            vec![1, 2, 3],
+            vec![1, 2, 3],
+            vec![1, 1, 4, 2, 6, 3],
+        ];
+        for (values, expected) in all.iter().zip(expected) {
+            assert_eq!(*values.borrow(), expected);
+        }
+        assert_eq!(*pairs.borrow(), vec![(1, 1), (1, 2), (2, 3)]);
+        assert_eq!(*groups.borrow(), vec![vec![1, 1], vec![2, 3]]);
+    }
+
+    #[test]
+    fn flows_emit_into_handlers() {
+        let eh = EH::new_ehrc();
+        let out = EH::new_ehrc();
+        let em = DEm::<TestTags>::new_emrc(None);
+        let nums = numbers(&eh);
+
+        // Derived events flow into another handler, and back into the source one
+        let derived = nums.map(|n| n * 2).filter(|n| *n > 2).map(T4).into_emitter(&out);
+        nums.filter(|n| *n == 3).map(|_| T1).emit_into(&eh, em);
+
+        feed(&eh, [T4(1), T4(2), T4(3)]);
+        assert_eq!(out.borrow().get_stack_tags(), vec![Some(T4(4)), Some(T4(6))]);
+        assert_eq!(out.borrow().get_stack_emitters(), vec![derived.clone(), derived]);
+        assert_eq!(eh.borrow().get_stack_tags(), vec![Some(T1)]);
+    }
+
+    #[test]
+    fn dropped_flows_unsubscribe() {
+        let eh = EH::new_ehrc();
+        let nums = numbers(&eh);
+        let big = nums.map(|n| n * 2);
+        let values = collect(&big);
+        assert_eq!(eh.borrow().get_observer_count(), 1);
+
+        // Derived flows keep their source subscribed, the last one dropped unsubscribes it
+        drop(nums);
+        assert_eq!(eh.borrow().get_observer_count(), 1);
+        feed(&eh, [T4(5)]);
+        assert_eq!(*values.borrow(), vec![10]);
+        drop(big);
+        assert_eq!(eh.borrow().get_observer_count(), 0);
+    }
+
+    #[test]
+    fn reentrant_sends() {
+        let (first, second) = (EH::new_ehrc(), EH::new_ehrc());
+        let em = DEm::<TestTags>::new_emrc(None);
+        second.borrow_mut().emit(em, T4(2));
+        let merged = numbers(&first).merge(&numbers(&second));
+
+        // Consuming `second` from a subscriber sends into `merged` while it is sending
+        let (seen, late) = (Rc::new(RefCell::new(vec![])), Rc::new(RefCell::new(None)));
+        let (sink, late_sink, flow) = (seen.clone(), late.clone(), merged.clone());
+        merged.for_each(move |n| {
+            sink.borrow_mut().push(n);
+            if n == 1 {
+                second.borrow_mut().consume_next_event();
+                *late_sink.borrow_mut() = Some(collect(&flow));
+            }
+        });
+
+        feed(&first, [T4(1), T4(3)]);
+        // Queued values wait for the running subscribers, new subscribers get the next value
+        assert_eq!(*seen.borrow(), vec![1, 2, 3]);
+        assert_eq!(*late.borrow().as_ref().unwrap().borrow(), vec![2, 3]);
+    }
+}
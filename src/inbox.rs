@@ -0,0 +1,72 @@
+use std::rc::Weak;
+use crate::{prelude::*, event::Event, event_handler::EventHandler};
+
+/// Handle for pushing events into an `EventHandler` from anywhere,
+/// including from inside a listener while the handler is dispatching.
+///
+/// If the handler is free the event goes straight onto its stack, otherwise
+/// it is queued and moved onto the stack once the current broadcast is done.
+#[derive(Clone)]
+pub struct Inbox<T: Tag, I: Id> {
+    handler_id: usize,
+    handler: Weak<RefCell<EventHandler<T, I>>>,
+    queue: Rc<RefCell<Vec<Event<T, I>>>>,
+}
+
+impl<T: Tag, I: Id> Debug for Inbox<T, I> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Inbox")
+            .field("handler id", &self.handler_id)
+            .field("queued", &self.queue.borrow().len())
+            .finish()
+    }
+}
+
+impl<T: Tag, I: Id> PartialEq for Inbox<T, I> {
+    fn eq(&self, other: &Self) -> bool {
+        self.handler_id == other.handler_id
+    }
+}
+
+impl<T: Tag, I: Id> Inbox<T, I> {
+    /// Panics if `eh` is mutably borrowed, like `RefCell::borrow`
+    pub fn new(eh: &EHRc<T, I>) -> Self {
+        let handler = eh.borrow();
+        Self { handler_id: handler.get_id(), handler: Rc::downgrade(eh), queue: handler.get_inbox_queue() }
+    }
+    pub fn get_handler_id(&self) -> usize {
+        self.handler_id
+    }
+    pub(crate) fn get_handler(&self) -> Option<EHRc<T, I>> {
+        self.handler.upgrade()
+    }
+    pub fn is_connected(&self) -> bool {
+        self.handler.strong_count() > 0
+    }
+    /// Events a full stack rejects become `Overflow` dead letters of the handler
+    pub fn push(&self, event: Event<T, I>) {
+        // Events sent to a handler that no longer exists are dropped
+        if let Some(eh) = self.handler.upgrade() {
+            match eh.try_borrow_mut() {
+                Ok(mut eh) => eh.push_event(Some(event)),
+                Err(_) => self.queue.borrow_mut().push(event),
+            }
+        }
+    }
+    /// Like `push`, but returns whether the handler took the event, which it does not
+    /// if it was dropped or its full stack rejected the event.
+    /// Queued events are taken, whatever happens to them once the handler is free.
+    pub fn try_push(&self, event: Event<T, I>) -> bool {
+        let Some(eh) = self.handler.upgrade() else { return false };
+        match eh.try_borrow_mut() {
+            Ok(mut eh) => eh.try_push_event(event).is_ok(),
+            Err(_) => {
+                self.queue.borrow_mut().push(event);
+                true
+            }
+        }
+    }
+    pub fn emit(&self, emitter: EmRC<I>, tag: T) {
+        self.push(Event::new(emitter, Some(tag)));
+    }
+}
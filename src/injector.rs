@@ -0,0 +1,176 @@
+use std::{sync::mpsc::{self, Receiver, Sender}, time::Duration};
+use crate::{prelude::*, event::Event, event_handler::EventHandler};
+
+/// Channel between the `Injector`s of a handler and the handler itself
+#[derive(Clone)]
+pub(crate) struct Injection<T: Tag, I: Id> {
+    sender: Sender<(I, T)>,
+    receiver: Rc<Receiver<(I, T)>>,
+}
+
+impl<T: Tag, I: Id> Debug for Injection<T, I> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Injection")
+    }
+}
+
+impl<T: Tag, I: Id> Injection<T, I> {
+    pub(crate) fn new() -> Self {
+        let (sender, receiver) = mpsc::channel();
+        Self { sender, receiver: Rc::new(receiver) }
+    }
+}
+
+/// `Send` handle through which other threads feed (emitter id, tag)
+/// pairs to an `EventHandler` living on its own thread
+#[derive(Clone)]
+pub struct Injector<T: Tag, I: Id> {
+    handler_id: usize,
+    sender: Sender<(I, T)>,
+}
+
+impl<T: Tag, I: Id> Debug for Injector<T, I> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Injector")
+            .field("handler id", &self.handler_id)
+            .finish()
+    }
+}
+
+impl<T: Tag, I: Id> Injector<T, I> {
+    pub fn get_handler_id(&self) -> usize {
+        self.handler_id
+    }
+    /// Fails with the pair if the handler has been dropped
+    pub fn inject(&self, emitter_id: I, tag: T) -> Result<(), (I, T)> {
+        self.sender.send((emitter_id, tag)).map_err(|e| e.0)
+    }
+}
+
+/// Outcome of moving injected pairs onto a handler's stack
+#[derive(Debug, Clone, PartialEq)]
+pub struct Injected<T: Tag, I: Id> {
+    pub pushed: usize,
+    /// Pairs whose emitter id is not registered with the handler
+    pub unresolved: Vec<(I, T)>,
+    /// Pairs whose events the handler's full stack rejected
+    pub rejected: Vec<(I, T)>,
+}
+
+impl<T: Tag, I: Id> EventHandler<T, I> {
+    pub fn get_injector(&mut self) -> Injector<T, I> {
+        let handler_id = self.get_id();
+        Injector { handler_id, sender: self.get_injection().sender.clone() }
+    }
+    fn push_injected(&mut self, pairs: Vec<(I, T)>) -> Injected<T, I> {
+        let mut injected = Injected { pushed: 0, unresolved: vec![], rejected: vec![] };
+        for (id, tag) in pairs {
+            match self.get_emitter_by_id(&id) {
+                Some(emitter) => match self.try_push_event(Event::new(emitter, Some(tag))) {
+                    Ok(()) => injected.pushed += 1,
+                    Err(_) => injected.rejected.push((id, tag)),
+                },
+                None => injected.unresolved.push((id, tag)),
+            }
+        }
+        injected
+    }
+    /// Pushes everything injected so far as events from the registered emitters
+    pub fn drain_injected(&mut self) -> Injected<T, I> {
+        let pairs = self.get_injection().receiver.try_iter().collect();
+        self.push_injected(pairs)
+    }
+    /// Blocks until something is injected, or `timeout` runs out, then drains every injected pair
+    pub fn wait_injected(&mut self, timeout: Option<Duration>) -> Injected<T, I> {
+        let receiver = self.get_injection().receiver.clone();
+        // The handler holds a sender itself, so the channel never disconnects
+        let first = match timeout {
+            Some(timeout) => receiver.recv_timeout(timeout).ok(),
+            None => receiver.recv().ok(),
+        };
+        let pairs = first.into_iter().chain(receiver.try_iter()).collect();
+        self.push_injected(pairs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use crate::{def_emitter::DefEmitter as DEm, tests::TestTags::{self, *}};
+    use super::*;
+
+    #[test]
+    fn register_emitters() {
+        let mut eh = EventHandler::<TestTags, usize>::new();
+        let sensor = DEm::<TestTags>::new_emrc(None);
+        eh.register_emitter(sensor.clone()).unwrap();
+        assert!(eh.register_emitter(sensor.clone()).is_err());
+        assert_eq!(eh.get_emitter_by_id(&sensor.borrow().get_id()), Some(sensor.clone()));
+
+        assert!(eh.unregister_emitter(&sensor));
+        assert!(!eh.unregister_emitter(&sensor));
+        assert!(eh.get_emitters().is_empty());
+    }
+
+    #[test]
+    fn inject_from_other_threads() {
+        let eh = EventHandler::<TestTags, usize>::new_ehrc();
+        let sensor = DEm::<TestTags>::new_emrc(None);
+        let sensor_id = sensor.borrow().get_id();
+        eh.borrow_mut().register_emitter(sensor.clone()).unwrap();
+        let injector = eh.borrow_mut().get_injector();
+
+        let workers: Vec<_> = (0..4).map(|i| {
+            let injector = injector.clone();
+            std::thread::spawn(move || injector.inject(sensor_id, T4(i)).unwrap())
+        }).collect();
+        for w in workers {
+            w.join().unwrap();
+        }
+        injector.inject(usize::MAX, T1).unwrap();
+
+        let injected = eh.borrow_mut().drain_injected();
+        assert_eq!(injected.pushed, 4);
+        assert_eq!(injected.unresolved, vec![(usize::MAX, T1)]);
+        assert!(eh.borrow().get_stack().iter().all(|e| e.get_emitter() == sensor));
+        assert_eq!(eh.borrow_mut().drain_injected().pushed, 0);
+    }
+
+    #[test]
+    fn wait_for_injected_events() {
+        let eh = EventHandler::<TestTags, usize>::new_ehrc();
+        let sensor = DEm::<TestTags>::new_emrc(None);
+        let sensor_id = sensor.borrow().get_id();
+        eh.borrow_mut().register_emitter(sensor.clone()).unwrap();
+        let injector = eh.borrow_mut().get_injector();
+
+        let worker = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            injector.inject(sensor_id, T2).unwrap();
+        });
+        let injected = eh.borrow_mut().wait_injected(Some(Duration::from_secs(5)));
+        worker.join().unwrap();
+        assert_eq!(injected.pushed, 1);
+        assert_eq!(eh.borrow().peek_next_tag(), Some(T2));
+
+        assert_eq!(eh.borrow_mut().wait_injected(Some(Duration::from_millis(1))).pushed, 0);
+    }
+
+    #[test]
+    fn full_stacks_reject_injected_events() {
+        let mut eh = EventHandler::<TestTags, usize>::new();
+        let sensor = DEm::<TestTags>::new_emrc(None);
+        let sensor_id = sensor.borrow().get_id();
+        eh.register_emitter(sensor).unwrap();
+        eh.set_capacity(Some(1));
+        let injector = eh.get_injector();
+
+        for tag in [T1, T2, T3] {
+            injector.inject(sensor_id, tag).unwrap();
+        }
+        let injected = eh.drain_injected();
+        assert_eq!(injected.pushed, 1);
+        assert_eq!(injected.rejected, vec![(sensor_id, T2), (sensor_id, T3)]);
+        assert_eq!(eh.get_stack_tags(), vec![Some(T1)]);
+    }
+}
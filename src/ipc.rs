@@ -0,0 +1,608 @@
+use std::{
+    collections::VecDeque,
+    io::{self, ErrorKind, Read, Write},
+    marker::PhantomData,
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+use crate::{prelude::*, event::Event, listener::TriggerError, IDCOUNTER};
+use crate::codec::{self, TagCodec};
+
+/// Frames are a little-endian `u32` body length, then a body made of the `u64` emitter id,
+/// the event's timestamp and headers as written by `codec::encode_metadata`, and the encoded tag
+const HEADER_LEN: usize = 4;
+const EMITTER_LEN: usize = 8;
+const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Largest frame body a new bridge sends or accepts, in bytes
+pub const DEFAULT_MAX_FRAME_LEN: usize = 1 << 20;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReconnectPolicy {
+    /// Minimum time between two attempts at reconnecting
+    pub retry_every: Duration,
+    /// Frames kept while disconnected, the oldest are dropped first
+    pub max_pending: usize,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self { retry_every: Duration::from_millis(100), max_pending: 1024 }
+    }
+}
+
+#[derive(Debug)]
+enum Endpoint {
+    Server(UnixListener),
+    Client(PathBuf),
+}
+
+/// Stands in for an emitter of the other process
+#[derive(Debug, Clone, PartialEq)]
+pub struct RemoteEmitter {
+    id: usize,
+    remote_id: u64,
+}
+
+impl EmitObj<usize> for RemoteEmitter {
+    fn get_id(&self) -> usize {
+        self.id
+    }
+}
+
+impl RemoteEmitter {
+    pub fn get_remote_id(&self) -> u64 {
+        self.remote_id
+    }
+}
+
+#[derive(Debug)]
+struct Link {
+    endpoint: Endpoint,
+    stream: Option<UnixStream>,
+    policy: ReconnectPolicy,
+    last_attempt: Option<Instant>,
+    connections: usize,
+    read_buf: Vec<u8>,
+    max_frame_len: usize,
+    pending: VecDeque<Vec<u8>>,
+    dropped: usize,
+    /// Local stand-ins of the remote emitters seen so far, by remote id
+    proxies: Vec<(u64, EmRC<usize>)>,
+}
+
+impl Link {
+    fn connect(&mut self) -> bool {
+        if self.stream.is_some() {
+            return true;
+        }
+        if self.last_attempt.is_some_and(|t| t.elapsed() < self.policy.retry_every) {
+            return false;
+        }
+        self.last_attempt = Some(Instant::now());
+
+        let stream = match &self.endpoint {
+            Endpoint::Server(listener) => listener.accept().map(|(s, _)| s),
+            Endpoint::Client(path) => UnixStream::connect(path),
+        };
+        // Accepted streams are blocking, writes give up after a while
+        // rather than stalling the handler on a peer that stopped reading
+        let stream = stream.and_then(|s| s.set_nonblocking(false).map(|_| s))
+            .and_then(|s| s.set_write_timeout(Some(WRITE_TIMEOUT)).map(|_| s));
+        match stream {
+            Ok(stream) => {
+                self.read_buf.clear();
+                self.stream = Some(stream);
+                self.connections += 1;
+                self.last_attempt = None;
+                self.flush();
+                self.stream.is_some()
+            }
+            Err(_) => false,
+        }
+    }
+    fn disconnect(&mut self) {
+        self.stream = None;
+        self.last_attempt = Some(Instant::now());
+    }
+    fn send(&mut self, frame: Vec<u8>) {
+        if self.pending.len() == self.policy.max_pending {
+            self.pending.pop_front();
+            self.dropped += 1;
+        }
+        self.pending.push_back(frame);
+        if self.connect() {
+            self.flush();
+        }
+    }
+    fn flush(&mut self) {
+        while let (Some(stream), Some(frame)) = (self.stream.as_mut(), self.pending.front()) {
+            if stream.write_all(frame).is_err() {
+                // The frame stays pending and is sent again after reconnecting
+                self.disconnect();
+                return;
+            }
+            self.pending.pop_front();
+        }
+    }
+    /// Reads whatever is available without blocking into complete frame bodies.
+    /// A frame over `max_frame_len` fails the connection, as the stream cannot be trusted past it.
+    fn receive(&mut self, bodies: &mut Vec<Vec<u8>>) -> io::Result<()> {
+        if !self.connect() {
+            return Ok(());
+        }
+        let Some(stream) = self.stream.as_mut() else { return Ok(()) };
+
+        let mut closed = stream.set_nonblocking(true).is_err();
+        let mut chunk = [0; 4096];
+        while !closed {
+            match stream.read(&mut chunk) {
+                Ok(0) => closed = true,
+                Ok(n) => self.read_buf.extend_from_slice(&chunk[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(_) => closed = true,
+            }
+        }
+        closed |= stream.set_nonblocking(false).is_err();
+
+        while self.read_buf.len() >= HEADER_LEN {
+            let len = u32::from_le_bytes(self.read_buf[..HEADER_LEN].try_into().unwrap()) as usize;
+            if len > self.max_frame_len {
+                self.read_buf.clear();
+                self.disconnect();
+                return Err(io::Error::new(ErrorKind::InvalidData, format!("IpcBridge received a {} byte frame, over the {} byte maximum", len, self.max_frame_len)));
+            }
+            if self.read_buf.len() < HEADER_LEN + len {
+                break;
+            }
+            bodies.push(self.read_buf[HEADER_LEN..HEADER_LEN + len].to_vec());
+            self.read_buf.drain(..HEADER_LEN + len);
+        }
+        if closed {
+            self.disconnect();
+        }
+        Ok(())
+    }
+    fn proxy(&mut self, remote_id: u64) -> EmRC<usize> {
+        if let Some((_, em)) = self.proxies.iter().find(|(id, _)| *id == remote_id) {
+            return em.clone();
+        }
+        let id = IDCOUNTER.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        let em = EmRC(Rc::new(RefCell::new(RemoteEmitter { id, remote_id })));
+        self.proxies.push((remote_id, em.clone()));
+        em
+    }
+    fn is_proxy(&self, emitter: &EmRC<usize>) -> bool {
+        self.proxies.iter().any(|(_, em)| em == emitter)
+    }
+}
+
+/// One end of a Unix domain socket connection carrying events between
+/// the `EventHandler`s of two processes.
+///
+/// Local events reach the socket through a `BridgeListener` and remote
+/// events reach the local handler through a `BridgeEmitter`. Remote emitters
+/// show up locally as `RemoteEmitter`s, whose events are never sent back.
+/// Dropped connections are re-established, with the server accepting a new
+/// client and the client reconnecting, as the bridge keeps being used.
+///
+/// The bridge keeps the `RemoteEmitter` of every remote emitter id it received
+/// events from for as long as it lives, so that the events of one remote emitter
+/// always come from the same local one. Peers with an unbounded number of
+/// emitters should be given a new bridge from time to time.
+pub struct IpcBridge<T: Tag, C: TagCodec<T>> {
+    link: Rc<RefCell<Link>>,
+    codec: Rc<C>,
+    _tags: PhantomData<T>,
+}
+
+impl<T: Tag, C: TagCodec<T>> Debug for IpcBridge<T, C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("IpcBridge")
+            .field("link", &self.link.borrow())
+            .finish()
+    }
+}
+
+impl<T: Tag, C: TagCodec<T>> IpcBridge<T, C> {
+    fn new(endpoint: Endpoint, codec: C, policy: ReconnectPolicy) -> Self {
+        let link = Link {
+            endpoint,
+            stream: None,
+            policy,
+            last_attempt: None,
+            connections: 0,
+            read_buf: Vec::new(),
+            max_frame_len: DEFAULT_MAX_FRAME_LEN,
+            pending: VecDeque::new(),
+            dropped: 0,
+            proxies: Vec::new(),
+        };
+        Self { link: Rc::new(RefCell::new(link)), codec: Rc::new(codec), _tags: PhantomData }
+    }
+    /// Server end, replacing any stale socket file at `path`
+    pub fn listen(path: impl AsRef<Path>, codec: C, policy: ReconnectPolicy) -> io::Result<Self> {
+        let path = path.as_ref();
+        if path.exists() {
+            std::fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        listener.set_nonblocking(true)?;
+        Ok(Self::new(Endpoint::Server(listener), codec, policy))
+    }
+    /// Client end, the server does not need to be up yet
+    pub fn connect(path: impl AsRef<Path>, codec: C, policy: ReconnectPolicy) -> Self {
+        let bridge = Self::new(Endpoint::Client(path.as_ref().to_path_buf()), codec, policy);
+        bridge.link.borrow_mut().connect();
+        bridge
+    }
+    pub fn is_connected(&self) -> bool {
+        self.link.borrow().stream.is_some()
+    }
+    /// How many times a connection was established, including the first one
+    pub fn get_connection_count(&self) -> usize {
+        self.link.borrow().connections
+    }
+    pub fn get_pending_count(&self) -> usize {
+        self.link.borrow().pending.len()
+    }
+    /// Frames dropped while disconnected because too many were pending
+    pub fn get_dropped_count(&self) -> usize {
+        self.link.borrow().dropped
+    }
+    /// Caps the body of the frames sent and accepted, in bytes, at most `u32::MAX`.
+    /// Defaults to `DEFAULT_MAX_FRAME_LEN`, both processes should use the same.
+    pub fn set_max_frame_len(&mut self, len: usize) {
+        self.link.borrow_mut().max_frame_len = len.min(u32::MAX as usize);
+    }
+    pub fn get_max_frame_len(&self) -> usize {
+        self.link.borrow().max_frame_len
+    }
+    /// Remote emitters events were received from so far
+    pub fn get_remote_emitter_count(&self) -> usize {
+        self.link.borrow().proxies.len()
+    }
+    pub fn bridge_listener(&self, triggers: Vec<T>) -> BridgeListener<T, C> {
+        BridgeListener {
+            id: IDCOUNTER.fetch_add(1, std::sync::atomic::Ordering::SeqCst),
+            triggers,
+            link: self.link.clone(),
+            codec: self.codec.clone(),
+        }
+    }
+    pub fn bridge_emitter(&self, eh: &EHRc<T, usize>) -> BridgeEmitter<T, C> {
+        BridgeEmitter { link: self.link.clone(), codec: self.codec.clone(), inbox: Inbox::new(eh), undecodable: 0, rejected: 0 }
+    }
+}
+
+/// Sends the local events it is triggered by to the other process.
+/// Events it fails while disconnected stay pending and become `Deferred` dead letters,
+/// events too large for a frame are not sent and become `AllFailed` ones.
+pub struct BridgeListener<T: Tag, C: TagCodec<T>> {
+    id: usize,
+    triggers: Vec<T>,
+    link: Rc<RefCell<Link>>,
+    codec: Rc<C>,
+}
+
+impl<T: Tag, C: TagCodec<T>> Clone for BridgeListener<T, C> {
+    fn clone(&self) -> Self {
+        Self { id: self.id, triggers: self.triggers.clone(), link: self.link.clone(), codec: self.codec.clone() }
+    }
+}
+
+impl<T: Tag, C: TagCodec<T>> Debug for BridgeListener<T, C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BridgeListener")
+            .field("id", &self.id)
+            .field("triggers", &self.triggers)
+            .finish()
+    }
+}
+
+impl<T: Tag, C: TagCodec<T>> EmitObj<usize> for BridgeListener<T, C> {
+    fn get_id(&self) -> usize {
+        self.id
+    }
+}
+
+impl<T: Tag, C: TagCodec<T> + 'static> IListener<T, usize> for BridgeListener<T, C> {
+    fn get_triggers(&self) -> Vec<&T> {
+        self.triggers.iter().collect()
+    }
+    fn has_trigger(&self, tag: &T) -> bool {
+        self.triggers.contains(tag)
+    }
+    fn on_triggers(&self, triggers: Vec<Event<T, usize>>) {
+        let _ = self.try_on_triggers(triggers);
+    }
+    fn try_on_triggers(&self, triggers: Vec<Event<T, usize>>) -> Result<(), TriggerError> {
+        let mut link = self.link.borrow_mut();
+        let mut oversized = vec![];
+        for e in triggers {
+            let Some(tag) = e.get_tag() else { continue };
+            if link.is_proxy(&e.get_emitter()) {
+                continue;
+            }
+            let mut body = (e.get_emitter().borrow().get_id() as u64).to_le_bytes().to_vec();
+            codec::encode_metadata(&e, &mut body);
+            self.codec.encode(&tag, &mut body);
+            if body.len() > link.max_frame_len {
+                oversized.push(format!("{:?} takes {} bytes", tag, body.len()));
+                continue;
+            }
+            let mut frame = (body.len() as u32).to_le_bytes().to_vec();
+            frame.extend(body);
+            link.send(frame);
+        }
+        if !oversized.is_empty() {
+            return Err(TriggerError::Failed(format!("IpcBridge frames are at most {} bytes: {}", link.max_frame_len, oversized.join(", "))));
+        }
+        match link.stream {
+            Some(_) => Ok(()),
+            None => Err(TriggerError::Deferred(format!("IpcBridge disconnected, {} frames pending", link.pending.len()))),
+        }
+    }
+    fn as_lirc(&self) -> LiRC<T, usize> {
+        LiRC(Rc::new(RefCell::new(self.clone())))
+    }
+    fn into_lirc(self) -> Result<LiRC<T, usize>, &'static str> {
+        Ok(LiRC(Rc::new(RefCell::new(self))))
+    }
+    fn try_into_lirc(self) -> Option<LiRC<T, usize>> {
+        Some(LiRC(Rc::new(RefCell::new(self))))
+    }
+    fn as_emrc(&self) -> EmRC<usize> {
+        EmRC(Rc::new(RefCell::new(self.clone())))
+    }
+    fn into_emrc(self) -> EmRC<usize> {
+        EmRC(Rc::new(RefCell::new(self)))
+    }
+}
+
+/// Pushes the events received from the other process into a local handler.
+/// Events keep the timestamp and headers they were sent with.
+pub struct BridgeEmitter<T: Tag, C: TagCodec<T>> {
+    link: Rc<RefCell<Link>>,
+    codec: Rc<C>,
+    inbox: Inbox<T, usize>,
+    undecodable: usize,
+    rejected: usize,
+}
+
+impl<T: Tag, C: TagCodec<T>> Debug for BridgeEmitter<T, C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BridgeEmitter")
+            .field("inbox", &self.inbox)
+            .field("undecodable", &self.undecodable)
+            .field("rejected", &self.rejected)
+            .finish()
+    }
+}
+
+impl<T: Tag, C: TagCodec<T>> BridgeEmitter<T, C> {
+    /// Reads the events received so far without blocking, reconnecting if needed,
+    /// and returns how many were pushed into the handler. See `try_poll`.
+    pub fn poll(&mut self) -> usize {
+        self.receive().0
+    }
+    /// Like `poll`, but fails once a frame over the maximum frame length is received.
+    /// The connection is then dropped, along with what was left to read,
+    /// after pushing the events received before that frame.
+    pub fn try_poll(&mut self) -> io::Result<usize> {
+        match self.receive() {
+            (_, Some(e)) => Err(e),
+            (pushed, None) => Ok(pushed),
+        }
+    }
+    fn receive(&mut self) -> (usize, Option<io::Error>) {
+        let mut bodies = vec![];
+        let error = {
+            let mut link = self.link.borrow_mut();
+            link.flush();
+            link.receive(&mut bodies).err()
+        };
+
+        let mut pushed = 0;
+        for body in bodies {
+            let decoded = body.split_at_checked(EMITTER_LEN).and_then(|(remote_id, rest)| {
+                let (metadata, tag) = codec::decode_metadata(rest)?;
+                Some((u64::from_le_bytes(remote_id.try_into().unwrap()), metadata, self.codec.decode(tag)?))
+            });
+            let Some((remote_id, metadata, tag)) = decoded else {
+                self.undecodable += 1;
+                continue;
+            };
+            if !self.inbox.is_connected() {
+                self.rejected += 1;
+                continue;
+            }
+            let emitter = self.link.borrow_mut().proxy(remote_id);
+            if self.inbox.try_push(metadata.apply(Event::new(emitter, Some(tag)))) {
+                pushed += 1;
+            } else {
+                self.rejected += 1;
+            }
+        }
+        (pushed, error)
+    }
+    /// Frames dropped because their tag could not be decoded
+    pub fn get_undecodable_count(&self) -> usize {
+        self.undecodable
+    }
+    /// Frames the handler did not take, because it was dropped or its stack was full
+    pub fn get_rejected_count(&self) -> usize {
+        self.rejected
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::ErrorKind, path::PathBuf};
+    use crate::{
+        event_handler::EventHandler as EH,
+        def_emitter::DefEmitter as DEm,
+        dead_letter::{DeadLetterReason, DeadLetterSink},
+        tests::{TestTags::{self, *}, Recorder, TestCodec},
+    };
+    use super::*;
+
+    const POLICY: ReconnectPolicy = ReconnectPolicy { retry_every: Duration::ZERO, max_pending: 1024 };
+
+    fn socket_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("event_handler_{}_{}.sock", name, std::process::id()))
+    }
+
+    /// Daemon handler sending T1, T4(7) and T5 over a server bridge, collecting its dead letters
+    fn daemon(path: &PathBuf) -> (EHRc<TestTags, usize>, IpcBridge<TestTags, TestCodec>) {
+        let daemon = EH::<TestTags, usize>::new_ehrc();
+        daemon.borrow_mut().set_dead_letter_sink(DeadLetterSink::Collect);
+        let server = IpcBridge::listen(path, TestCodec, POLICY).unwrap();
+        daemon.borrow_mut().add_listener(server.bridge_listener(vec![T1, T4(7), T5("x")]).into_lirc().unwrap()).unwrap();
+        (daemon, server)
+    }
+
+    fn dead_letter_reasons(eh: &EHRc<TestTags, usize>) -> Vec<DeadLetterReason> {
+        eh.borrow_mut().take_dead_letters().iter().map(|l| l.reason).collect()
+    }
+
+    #[test]
+    fn events_cross_the_bridge() {
+        let path = socket_path("cross");
+        let (daemon, server) = daemon(&path);
+        let mut from_ui = server.bridge_emitter(&daemon);
+        let em = DEm::<TestTags>::new_emrc(None);
+
+        let ui = EH::<TestTags, usize>::new_ehrc();
+        let client = IpcBridge::connect(&path, TestCodec, POLICY);
+        let on_ui = Recorder::new(vec![T1, T4(7)], None);
+        ui.borrow_mut().add_listener(on_ui.as_lirc()).unwrap();
+        ui.borrow_mut().add_listener(client.bridge_listener(vec![T1]).into_lirc().unwrap()).unwrap();
+        let mut from_daemon = client.bridge_emitter(&ui);
+        assert!(client.is_connected());
+
+        let sent = Event::new(em.clone(), Some(T1)).with_header("user", "ob");
+        daemon.borrow_mut().push_event(Some(sent.clone()));
+        daemon.borrow_mut().emit(em.clone(), T5("x"));
+        daemon.borrow_mut().consume_next_event();
+        daemon.borrow_mut().consume_next_event();
+        assert_eq!(from_daemon.poll(), 1);
+        assert_eq!(from_daemon.get_undecodable_count(), 1);
+        ui.borrow_mut().consume_next_event();
+        assert_eq!(on_ui.received_tags(), vec![Some(T1)]);
+        let received = on_ui.received.borrow()[0].clone();
+        assert_ne!(received.get_emitter(), em);
+        assert_eq!((received.get_timestamp(), received.get_header("user")), (sent.get_timestamp(), Some("ob")));
+        assert_eq!(client.get_remote_emitter_count(), 1);
+        // Events from the daemon are not echoed back
+        assert_eq!(from_ui.poll(), 0);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn frames_wait_for_a_reconnection() {
+        let path = socket_path("reconnect");
+        let (daemon, server) = daemon(&path);
+        let mut from_ui = server.bridge_emitter(&daemon);
+        let em = DEm::<TestTags>::new_emrc(None);
+
+        let client = IpcBridge::<TestTags, TestCodec>::connect(&path, TestCodec, POLICY);
+        from_ui.poll();
+        assert!(server.is_connected());
+        drop(client);
+        daemon.borrow_mut().emit(em.clone(), T4(7));
+        daemon.borrow_mut().consume_next_event();
+        from_ui.poll();
+        assert!(!server.is_connected());
+        assert_eq!(server.get_pending_count(), 1);
+        // The event is kept until the connection is back
+        assert_eq!(dead_letter_reasons(&daemon), vec![DeadLetterReason::Deferred]);
+
+        let ui = EH::<TestTags, usize>::new_ehrc();
+        let client = IpcBridge::connect(&path, TestCodec, POLICY);
+        let mut from_daemon = client.bridge_emitter(&ui);
+        from_ui.poll();
+        assert_eq!(server.get_connection_count(), 2);
+        assert_eq!(from_daemon.poll(), 1);
+        assert_eq!(ui.borrow().peek_next_tag(), Some(T4(7)));
+        assert_eq!(server.get_pending_count(), 0);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn oversized_frames() {
+        let path = socket_path("oversized");
+        let (daemon, mut server) = daemon(&path);
+        let mut from_ui = server.bridge_emitter(&daemon);
+        let em = DEm::<TestTags>::new_emrc(None);
+        let ui = EH::<TestTags, usize>::new_ehrc();
+        let mut client = IpcBridge::connect(&path, TestCodec, POLICY);
+        let mut from_daemon = client.bridge_emitter(&ui);
+        from_ui.poll();
+
+        // Frames over the sender's maximum are not sent
+        server.set_max_frame_len(8);
+        daemon.borrow_mut().emit(em.clone(), T1);
+        daemon.borrow_mut().consume_next_event();
+        assert_eq!(dead_letter_reasons(&daemon), vec![DeadLetterReason::AllFailed]);
+        assert_eq!(server.get_pending_count(), 0);
+
+        // and frames over the receiver's maximum drop the connection
+        server.set_max_frame_len(DEFAULT_MAX_FRAME_LEN);
+        client.set_max_frame_len(8);
+        daemon.borrow_mut().emit(em.clone(), T4(7));
+        daemon.borrow_mut().consume_next_event();
+        assert_eq!(from_daemon.try_poll().unwrap_err().kind(), ErrorKind::InvalidData);
+        assert!(!client.is_connected());
+        assert_eq!(ui.borrow().get_stack_len(), 0);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn frames_for_a_dropped_handler_are_rejected() {
+        let path = socket_path("rejected");
+        let (daemon, server) = daemon(&path);
+        let mut from_ui = server.bridge_emitter(&daemon);
+        let em = DEm::<TestTags>::new_emrc(None);
+        let ui = EH::<TestTags, usize>::new_ehrc();
+        let client = IpcBridge::connect(&path, TestCodec, POLICY);
+        let mut from_daemon = client.bridge_emitter(&ui);
+        from_ui.poll();
+
+        drop(ui);
+        daemon.borrow_mut().emit(em.clone(), T1);
+        daemon.borrow_mut().consume_next_event();
+        assert_eq!(from_daemon.poll(), 0);
+        assert_eq!(from_daemon.get_rejected_count(), 1);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn frames_for_a_full_stack_are_rejected() {
+        let path = socket_path("full");
+        let (daemon, server) = daemon(&path);
+        let mut from_ui = server.bridge_emitter(&daemon);
+        let em = DEm::<TestTags>::new_emrc(None);
+        let ui = EH::<TestTags, usize>::new_ehrc();
+        ui.borrow_mut().set_capacity(Some(1));
+        let client = IpcBridge::connect(&path, TestCodec, POLICY);
+        let mut from_daemon = client.bridge_emitter(&ui);
+        from_ui.poll();
+
+        for tag in [T1, T4(7)] {
+            daemon.borrow_mut().emit(em.clone(), tag);
+            daemon.borrow_mut().consume_next_event();
+        }
+        assert_eq!(from_daemon.poll(), 1);
+        assert_eq!(from_daemon.get_rejected_count(), 1);
+        assert_eq!(ui.borrow().get_stack_tags(), vec![Some(T1)]);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -3,12 +3,36 @@
 pub mod prelude;
 
 pub mod event;
+pub mod clock;
 pub mod emit_obj;
 pub mod event_handler;
 pub mod sub_event_handler;
 pub mod eh_parent;
 pub mod def_emitter;
 pub mod listener;
+pub mod inbox;
+pub mod state_machine;
+pub mod topic;
+pub mod run;
+pub mod causality;
+pub mod dead_letter;
+pub mod router;
+pub mod injector;
+pub mod codec;
+pub mod wal;
+pub mod undo;
+pub mod projection;
+pub mod flow;
+pub mod observable;
+pub mod relay;
+pub mod actor;
+pub mod parallel;
+pub mod overflow;
+pub mod sticky;
+#[cfg(feature = "async")]
+pub mod asynchronous;
+#[cfg(all(unix, feature = "ipc"))]
+pub mod ipc;
 
 pub static IDCOUNTER: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
 
@@ -19,11 +43,12 @@ mod tests {
         event::Event,
         event_handler::EventHandler as EH,
         def_emitter::DefEmitter as DEm,
-        listener::DefListener as DLi,
+        listener::{DefListener as DLi, TriggerError},
+        codec::TagCodec,
     };
 
     #[derive(Debug, PartialEq, Copy, Clone)]
-    enum TestTags {
+    pub(crate) enum TestTags {
         T1,
         T2,
         T3,
@@ -31,6 +56,103 @@ mod tests {
         T5(&'static str),
     }
 
+    /// Listener keeping every event it receives, optionally answering queries
+    /// or failing to handle events
+    #[derive(Clone)]
+    pub(crate) struct Recorder {
+        id: usize,
+        triggers: Vec<TestTags>,
+        reply: Option<TestTags>,
+        pub(crate) error: Option<&'static str>,
+        /// Whether `error` defers the events instead of failing them
+        pub(crate) deferred: bool,
+        pub(crate) received: Rc<RefCell<Vec<Event<TestTags, usize>>>>,
+    }
+
+    impl Recorder {
+        pub(crate) fn new(triggers: Vec<TestTags>, reply: Option<TestTags>) -> Self {
+            let id = crate::IDCOUNTER.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Self { id, triggers, reply, error: None, deferred: false, received: Rc::new(RefCell::new(vec![])) }
+        }
+        pub(crate) fn received_tags(&self) -> Vec<Option<TestTags>> {
+            self.received.borrow().iter().map(|e| e.get_tag()).collect()
+        }
+    }
+
+    impl EmitObj<usize> for Recorder {
+        fn get_id(&self) -> usize {
+            self.id
+        }
+    }
+
+    impl IListener<TestTags, usize> for Recorder {
+        fn get_triggers(&self) -> Vec<&TestTags> {
+            self.triggers.iter().collect()
+        }
+        fn has_trigger(&self, tag: &TestTags) -> bool {
+            self.triggers.contains(tag)
+        }
+        fn on_triggers(&self, triggers: Vec<Event<TestTags, usize>>) {
+            self.received.borrow_mut().extend(triggers);
+        }
+        fn try_on_triggers(&self, triggers: Vec<Event<TestTags, usize>>) -> Result<(), TriggerError> {
+            match self.error {
+                Some(e) if self.deferred => return Err(TriggerError::Deferred(e.to_string())),
+                Some(e) => return Err(TriggerError::Failed(e.to_string())),
+                None => {}
+            }
+            self.on_triggers(triggers);
+            Ok(())
+        }
+        fn on_query(&self, _query: &Event<TestTags, usize>) -> Option<TestTags> {
+            self.reply
+        }
+        fn as_lirc(&self) -> LiRC<TestTags, usize> {
+            LiRC(Rc::new(RefCell::new(self.clone())))
+        }
+        fn into_lirc(self) -> Result<LiRC<TestTags, usize>, &'static str> {
+            Ok(LiRC(Rc::new(RefCell::new(self))))
+        }
+        fn try_into_lirc(self) -> Option<LiRC<TestTags, usize>> {
+            Some(LiRC(Rc::new(RefCell::new(self))))
+        }
+        fn as_emrc(&self) -> EmRC<usize> {
+            EmRC(Rc::new(RefCell::new(self.clone())))
+        }
+        fn into_emrc(self) -> EmRC<usize> {
+            EmRC(Rc::new(RefCell::new(self)))
+        }
+    }
+
+    /// Codec for the tags without a `&str`, which it cannot decode
+    pub(crate) struct TestCodec;
+
+    impl TagCodec<TestTags> for TestCodec {
+        fn encode(&self, tag: &TestTags, buf: &mut Vec<u8>) {
+            use TestTags::*;
+            let (variant, value) = match tag {
+                T1 => (1, 0),
+                T2 => (2, 0),
+                T3 => (3, 0),
+                T4(n) => (4, *n),
+                T5(_) => (5, 0),
+            };
+            buf.push(variant);
+            buf.extend(value.to_le_bytes());
+        }
+        fn decode(&self, bytes: &[u8]) -> Option<TestTags> {
+            use TestTags::*;
+            let value = i32::from_le_bytes(bytes.get(1..5)?.try_into().ok()?);
+            match bytes[0] {
+                1 => Some(T1),
+                2 => Some(T2),
+                3 => Some(T3),
+                4 => Some(T4(value)),
+                _ => None,
+            }
+        }
+    }
+
     // *** Tests start here *** //
     #[test]
     fn empty_initializations() {
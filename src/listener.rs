@@ -2,12 +2,45 @@ use crate::def_emitter::DefEmitter;
 use crate::{prelude::*, event::Event};
 use crate::IDCOUNTER;
 
+/// Bumped whenever a listener's triggers change, see `triggers_changed`
+pub(crate) static TRIGGERS_GENERATION: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+/// To be called by listeners whose triggers change after being added to a handler,
+/// so handlers in `DispatchMode::Indexed` rebuild their tag index before the next dispatch
+pub fn triggers_changed() {
+    TRIGGERS_GENERATION.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+}
+
+/// Why `IListener::try_on_triggers` did not handle its events
+#[derive(Debug, Clone, PartialEq)]
+pub enum TriggerError {
+    /// The events were not handled
+    Failed(String),
+    /// The events were kept to be handled later, e.g. once a connection is back
+    Deferred(String),
+}
+
+impl Display for TriggerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TriggerError::Failed(e) | TriggerError::Deferred(e) => write!(f, "{}", e),
+        }
+    }
+}
+
 /// High-level trait to be implemented by all objects
 /// to be added as listeners to an event handler
 pub trait IListener<T: Tag, I: Id>: EmitObj<I> {
     fn get_triggers(&self) -> Vec<&T>;
     fn has_trigger(&self, tag: &T) -> bool;
     fn on_triggers(&self, triggers: Vec<Event<T, I>>);
+    /// Fallible `on_triggers`, events failed by every listener they reach become dead letters
+    fn try_on_triggers(&self, triggers: Vec<Event<T, I>>) -> Result<(), TriggerError> {
+        self.on_triggers(triggers);
+        Ok(())
+    }
+    /// Answer to a query event, `None` if the listener has nothing to say
+    fn on_query(&self, _query: &Event<T, I>) -> Option<T> { None }
     fn as_lirc(&self) -> LiRC<T, I>;
     fn into_lirc(self) -> Result<LiRC<T, I>, &'static str>;
     fn try_into_lirc(self) -> Option<LiRC<T, I>>;
@@ -36,9 +69,9 @@ impl<T: Tag, I: Id> PartialEq<dyn EmitObj<I>> for dyn IListener<T, I> {
     }
 }
 
-///
+/// Shared handle to a listener registered with an event handler
 #[derive(Clone, Debug)]
-pub struct LiRC<T: Tag, I: Id>(Rc<RefCell<dyn IListener<T, I>>>);
+pub struct LiRC<T: Tag, I: Id>(pub(crate) Rc<RefCell<dyn IListener<T, I>>>);
 
 impl<T: Tag, I: Id> Deref for LiRC<T, I> {
     type Target = Rc<RefCell<dyn IListener<T, I>>>;
@@ -71,9 +104,9 @@ impl<T: Tag> EmitObj<usize> for DefListener<T> {
     }
 }
 
-impl<T: Tag> Into<LiRC<T, usize>> for DefListener<T> {
-    fn into(self) -> LiRC<T, usize> {
-        LiRC(Rc::new(RefCell::new(self)))
+impl<T: Tag> From<DefListener<T>> for LiRC<T, usize> {
+    fn from(li: DefListener<T>) -> Self {
+        LiRC(Rc::new(RefCell::new(li)))
     }
 }
 
@@ -97,13 +130,7 @@ impl<T: Tag> IListener<T, usize> for DefListener<T> {
     fn has_trigger(&self, tag: &T) -> bool {
         self.triggers.contains(tag)
     }
-    fn on_triggers(&self, triggers: Vec<Event<T, usize>>) {
-        for t in triggers {
-            match t {
-                _ => {}
-            }
-        }
-    }
+    fn on_triggers(&self, _triggers: Vec<Event<T, usize>>) {}
     fn as_lirc(&self) -> LiRC<T, usize> {
         LiRC(Rc::new(RefCell::new(self.clone())))
     }
@@ -0,0 +1,254 @@
+use std::collections::VecDeque;
+use crate::{prelude::*, event::Event, IDCOUNTER};
+
+type ChangeFn<T, V> = Rc<dyn Fn(&V, &V) -> T>;
+
+/// Tag of the events an `Observable` pushes when its value changes
+#[derive(Clone)]
+pub enum ChangeTag<T: Tag, V> {
+    Fixed(T),
+    /// Computed from the old and the new value
+    With(ChangeFn<T, V>),
+}
+
+impl<T: Tag, V> Debug for ChangeTag<T, V> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChangeTag::Fixed(t) => write!(f, "Fixed({:?})", t),
+            ChangeTag::With(_) => write!(f, "With"),
+        }
+    }
+}
+
+struct ObservableState<T: Tag, V> {
+    id: usize,
+    value: V,
+    previous: Option<V>,
+    tag: ChangeTag<T, V>,
+    inboxes: Vec<Inbox<T, usize>>,
+    /// Old and new values of the last changes, by id of the event announcing them
+    changes: VecDeque<(usize, V, V)>,
+    history: usize,
+    suppressed: usize,
+}
+
+/// Value that pushes a change event into its connected handlers whenever it is set to
+/// something different, with itself as the emitter.
+///
+/// Clones share the same value and id. Listeners can look up the old and new values
+/// of the change an event announces with `get_change`.
+pub struct Observable<T: Tag, V: Clone + PartialEq + 'static> {
+    state: Rc<RefCell<ObservableState<T, V>>>,
+}
+
+impl<T: Tag, V: Clone + PartialEq + 'static> Clone for Observable<T, V> {
+    fn clone(&self) -> Self {
+        Self { state: self.state.clone() }
+    }
+}
+
+impl<T: Tag, V: Clone + PartialEq + Debug + 'static> Debug for Observable<T, V> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let state = self.state.borrow();
+        f.debug_struct("Observable")
+            .field("id", &state.id)
+            .field("value", &state.value)
+            .field("previous", &state.previous)
+            .field("tag", &state.tag)
+            .field("handler ids", &state.inboxes.iter().map(|i| i.get_handler_id()).collect::<Vec<usize>>())
+            .finish()
+    }
+}
+
+impl<T: Tag, V: Clone + PartialEq + 'static> EmitObj<usize> for Observable<T, V> {
+    fn get_id(&self) -> usize {
+        self.state.borrow().id
+    }
+}
+
+impl<T: Tag, V: Clone + PartialEq + 'static> Observable<T, V> {
+    fn with_tag(value: V, tag: ChangeTag<T, V>) -> Self {
+        Self {
+            state: Rc::new(RefCell::new(ObservableState {
+                id: IDCOUNTER.fetch_add(1, std::sync::atomic::Ordering::SeqCst),
+                value,
+                previous: None,
+                tag,
+                inboxes: Vec::new(),
+                changes: VecDeque::new(),
+                history: 64,
+                suppressed: 0,
+            })),
+        }
+    }
+    /// Announces every change with `tag`
+    pub fn new(value: V, tag: T) -> Self {
+        Self::with_tag(value, ChangeTag::Fixed(tag))
+    }
+    /// Announces every change with the tag `f` computes from the old and the new value
+    pub fn new_with(value: V, f: impl Fn(&V, &V) -> T + 'static) -> Self {
+        Self::with_tag(value, ChangeTag::With(Rc::new(f)))
+    }
+    pub fn as_emrc(&self) -> EmRC<usize> {
+        EmRC(Rc::new(RefCell::new(self.clone())))
+    }
+    pub fn connect(&mut self, eh: &EHRc<T, usize>) {
+        let inbox = Inbox::new(eh);
+        let mut state = self.state.borrow_mut();
+        if !state.inboxes.contains(&inbox) {
+            state.inboxes.push(inbox);
+        }
+    }
+    pub fn disconnect(&mut self, eh: &EHRc<T, usize>) -> bool {
+        let id = eh.borrow().get_id();
+        let mut state = self.state.borrow_mut();
+        let len = state.inboxes.len();
+        state.inboxes.retain(|i| i.get_handler_id() != id);
+        state.inboxes.len() != len
+    }
+    /// Ids of the handlers changes are pushed into, dropped handlers excluded
+    pub fn get_connected_handlers(&self) -> Vec<usize> {
+        self.state.borrow().inboxes.iter().filter(|i| i.is_connected()).map(|i| i.get_handler_id()).collect()
+    }
+    pub fn get(&self) -> V {
+        self.state.borrow().value.clone()
+    }
+    /// Value before the last change
+    pub fn get_previous(&self) -> Option<V> {
+        self.state.borrow().previous.clone()
+    }
+    /// Old and new values of the change `event` announces, if it is one of the recent ones
+    pub fn get_change(&self, event: &Event<T, usize>) -> Option<(V, V)> {
+        self.state.borrow().changes.iter()
+            .find(|(id, _, _)| *id == event.get_id())
+            .map(|(_, old, new)| (old.clone(), new.clone()))
+    }
+    /// How many recent changes `get_change` can look up
+    pub fn set_history(&mut self, history: usize) {
+        let mut state = self.state.borrow_mut();
+        state.history = history;
+        while state.changes.len() > history {
+            state.changes.pop_front();
+        }
+    }
+    /// Sets calls that left the value as it was, so pushed nothing
+    pub fn get_suppressed_count(&self) -> usize {
+        self.state.borrow().suppressed
+    }
+    /// Returns whether the value changed, in which case the change is pushed into every connected handler
+    pub fn set(&mut self, value: V) -> bool {
+        let events = {
+            let mut state = self.state.borrow_mut();
+            if state.value == value {
+                state.suppressed += 1;
+                return false;
+            }
+            let old = std::mem::replace(&mut state.value, value.clone());
+            let tag = match &state.tag {
+                ChangeTag::Fixed(t) => *t,
+                ChangeTag::With(f) => f(&old, &value),
+            };
+            state.inboxes.retain(|i| i.is_connected());
+            let events: Vec<(Inbox<T, usize>, Event<T, usize>)> = state.inboxes.iter()
+                .map(|i| (i.clone(), Event::new(self.as_emrc(), Some(tag))))
+                .collect();
+            for (_, e) in &events {
+                state.changes.push_back((e.get_id(), old.clone(), value.clone()));
+            }
+            let excess = state.changes.len().saturating_sub(state.history);
+            state.changes.drain(..excess);
+            state.previous = Some(old);
+            events
+        };
+
+        // Pushed once the value is released, for handlers reading it while pushing
+        for (inbox, e) in events {
+            inbox.push(e);
+        }
+        true
+    }
+    /// Sets the value to what `f` makes of it
+    pub fn update(&mut self, f: impl FnOnce(&V) -> V) -> bool {
+        let value = f(&self.state.borrow().value);
+        self.set(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{event_handler::EventHandler, tests::{TestTags::{self, *}, Recorder}};
+    use super::*;
+
+    type EH = EventHandler<TestTags, usize>;
+
+    #[test]
+    fn changes_are_pushed_once() {
+        let eh = EH::new_ehrc();
+        let recorder = Recorder::new(vec![T3], None);
+        eh.borrow_mut().add_listener(recorder.as_lirc()).unwrap();
+
+        let mut volume = Observable::new(0, T3);
+        volume.connect(&eh);
+        volume.connect(&eh);
+        assert_eq!(volume.get_connected_handlers(), vec![eh.borrow().get_id()]);
+
+        assert!(volume.set(5));
+        assert!(!volume.set(5));
+        assert_eq!(volume.get_suppressed_count(), 1);
+        assert!(volume.update(|v| v + 1));
+        assert_eq!((volume.get(), volume.get_previous()), (6, Some(5)));
+        eh.borrow_mut().run_until_empty(Default::default());
+        assert_eq!(recorder.received_tags(), vec![Some(T3), Some(T3)]);
+        assert_eq!(recorder.received.borrow()[0].get_emitter(), volume.as_emrc());
+    }
+
+    #[test]
+    fn change_lookups() {
+        let eh = EH::new_ehrc();
+        let recorder = Recorder::new(vec![T3], None);
+        eh.borrow_mut().add_listener(recorder.as_lirc()).unwrap();
+        let mut volume = Observable::new(0, T3);
+        volume.connect(&eh);
+
+        volume.set(5);
+        volume.set(6);
+        eh.borrow_mut().run_until_empty(Default::default());
+        let changes: Vec<_> = recorder.received.borrow().iter().map(|e| volume.get_change(e)).collect();
+        assert_eq!(changes, vec![Some((5, 6)), Some((0, 5))]);
+
+        // Only the most recent changes are kept
+        volume.set_history(1);
+        let changes: Vec<_> = recorder.received.borrow().iter().map(|e| volume.get_change(e)).collect();
+        assert_eq!(changes, vec![Some((5, 6)), None]);
+    }
+
+    #[test]
+    fn computed_change_tags() {
+        let eh = EH::new_ehrc();
+        let mut trend = Observable::new_with(0, |old, new| T4(if new > old { 1 } else { -1 }));
+        trend.connect(&eh);
+
+        trend.set(4);
+        trend.set(2);
+        assert_eq!(eh.borrow().get_stack_tags(), vec![Some(T4(1)), Some(T4(-1))]);
+    }
+
+    #[test]
+    fn disconnected_handlers() {
+        let (first, second) = (EH::new_ehrc(), EH::new_ehrc());
+        let mut volume = Observable::new(0, T3);
+        volume.connect(&first);
+        volume.connect(&second);
+        assert_eq!(volume.get_connected_handlers(), vec![first.borrow().get_id(), second.borrow().get_id()]);
+
+        assert!(volume.disconnect(&second));
+        assert!(!volume.disconnect(&second));
+        volume.set(7);
+        assert_eq!(first.borrow().get_stack_len(), 1);
+        assert_eq!(second.borrow().get_stack_len(), 0);
+
+        // Dropped handlers are left out
+        drop(first);
+        assert_eq!(volume.get_connected_handlers(), vec![]);
+    }
+}
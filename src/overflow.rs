@@ -0,0 +1,293 @@
+use crate::{prelude::*, event::Event, event_handler::EventHandler};
+
+type PriorityFn<T, I> = Rc<dyn Fn(&Event<T, I>) -> i64>;
+type OverflowFn<T, I> = Rc<dyn Fn(&Event<T, I>, &[Event<T, I>]) -> OverflowAction>;
+
+/// What a full stack does with one more event
+#[derive(Clone, Default)]
+pub enum OverflowPolicy<T: Tag, I: Id> {
+    /// Turn the new event away, `try_push_event` and the like hand it back as an error
+    /// and the other pushes bury it as an `Overflow` dead letter
+    #[default]
+    Reject,
+    /// Drop the pending event that was pushed first
+    DropOldest,
+    /// Drop the new event
+    DropNewest,
+    /// Drop whichever of the pending and new events has the lowest priority,
+    /// the one pushed first among equals
+    DropLowestPriority(PriorityFn<T, I>),
+    /// Let a callback decide from the new event and the stack
+    Callback(OverflowFn<T, I>),
+}
+
+impl<T: Tag, I: Id> Debug for OverflowPolicy<T, I> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OverflowPolicy::Reject => write!(f, "Reject"),
+            OverflowPolicy::DropOldest => write!(f, "DropOldest"),
+            OverflowPolicy::DropNewest => write!(f, "DropNewest"),
+            OverflowPolicy::DropLowestPriority(_) => write!(f, "DropLowestPriority"),
+            OverflowPolicy::Callback(_) => write!(f, "Callback"),
+        }
+    }
+}
+
+/// Decision of an `OverflowPolicy::Callback`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OverflowAction {
+    Reject,
+    DropNewest,
+    /// Drop the pending event at this position of the stack, counted from the bottom.
+    /// Positions past the top of the stack reject the new event instead.
+    Evict(usize),
+    /// Push the new event anyway, going over capacity
+    Grow,
+}
+
+/// Event that did not fit on a full stack
+#[derive(Debug, Clone, PartialEq)]
+pub struct StackFull<T: Tag, I: Id> {
+    pub event: Box<Event<T, I>>,
+    pub capacity: usize,
+    /// Position a callback asked to evict, if it was past the top of the stack
+    pub missed_eviction: Option<usize>,
+}
+
+impl<T: Tag, I: Id> Display for StackFull<T, I> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.missed_eviction {
+            Some(pos) => write!(f, "Stack of capacity {} is full and has no event at {} to evict", self.capacity, pos),
+            None => write!(f, "Stack of capacity {} is full", self.capacity),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct OverflowStats {
+    /// Events turned away, handed back to whoever pushed them or buried as dead letters
+    pub rejected: usize,
+    /// New events dropped
+    pub dropped: usize,
+    /// Pending events dropped to make room
+    pub evicted: usize,
+    /// Events pushed over capacity by a callback
+    pub grown: usize,
+}
+
+impl OverflowStats {
+    /// How many times an event was pushed onto a full stack
+    pub fn overflows(&self) -> usize {
+        self.rejected + self.dropped + self.evicted + self.grown
+    }
+}
+
+/// Outcome of fitting an event on the stack
+pub(crate) enum Room<T: Tag, I: Id> {
+    Push(Event<T, I>),
+    /// Push the event, the pending event with the given id was dropped for it
+    Evicted(Event<T, I>, usize),
+    Dropped,
+    Full(StackFull<T, I>),
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Bounds<T: Tag, I: Id> {
+    capacity: Option<usize>,
+    policy: OverflowPolicy<T, I>,
+    stats: OverflowStats,
+}
+
+impl<T: Tag, I: Id> Default for Bounds<T, I> {
+    fn default() -> Self {
+        Self { capacity: None, policy: OverflowPolicy::default(), stats: OverflowStats::default() }
+    }
+}
+
+impl<T: Tag, I: Id> Bounds<T, I> {
+    pub(crate) fn make_room(&mut self, stack: &mut Vec<Event<T, I>>, event: Event<T, I>) -> Room<T, I> {
+        let Some(capacity) = self.capacity else { return Room::Push(event) };
+        if stack.len() < capacity {
+            return Room::Push(event);
+        }
+
+        let action = match &self.policy {
+            OverflowPolicy::Reject => OverflowAction::Reject,
+            OverflowPolicy::DropOldest => OverflowAction::Evict(0),
+            OverflowPolicy::DropNewest => OverflowAction::DropNewest,
+            OverflowPolicy::DropLowestPriority(priority) => {
+                let lowest = stack.iter().enumerate().min_by_key(|(pos, e)| (priority(e), *pos));
+                match lowest {
+                    Some((pos, e)) if priority(e) <= priority(&event) => OverflowAction::Evict(pos),
+                    _ => OverflowAction::DropNewest,
+                }
+            }
+            OverflowPolicy::Callback(f) => f(&event, stack),
+        };
+
+        #[cfg(test)]
+        println!("Stack of capacity {} overflowed with {:?}: {:?}", capacity, event, action);
+
+        match action {
+            OverflowAction::Evict(pos) if pos < stack.len() => {
+                self.stats.evicted += 1;
+                Room::Evicted(event, stack.remove(pos).get_id())
+            }
+            OverflowAction::DropNewest => {
+                self.stats.dropped += 1;
+                Room::Dropped
+            }
+            OverflowAction::Grow => {
+                self.stats.grown += 1;
+                Room::Push(event)
+            }
+            OverflowAction::Evict(pos) => {
+                self.stats.rejected += 1;
+                Room::Full(StackFull { event: Box::new(event), capacity, missed_eviction: Some(pos) })
+            }
+            OverflowAction::Reject => {
+                self.stats.rejected += 1;
+                Room::Full(StackFull { event: Box::new(event), capacity, missed_eviction: None })
+            }
+        }
+    }
+}
+
+impl<T: Tag, I: Id> EventHandler<T, I> {
+    pub fn get_capacity(&self) -> Option<usize> {
+        self.get_bounds().capacity
+    }
+    /// Caps how many events can be pending, `None` for no cap.
+    /// Lowering the capacity below the stack's length only affects later pushes.
+    pub fn set_capacity(&mut self, capacity: Option<usize>) {
+        self.get_bounds_mut().capacity = capacity;
+    }
+    pub fn get_overflow_policy(&self) -> &OverflowPolicy<T, I> {
+        &self.get_bounds().policy
+    }
+    pub fn set_overflow_policy(&mut self, policy: OverflowPolicy<T, I>) {
+        self.get_bounds_mut().policy = policy;
+    }
+    pub fn get_overflow_stats(&self) -> OverflowStats {
+        self.get_bounds().stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        def_emitter::DefEmitter as DEm,
+        dead_letter::{DeadLetterReason, DeadLetterSink},
+        tests::TestTags::{self, *},
+    };
+    use super::*;
+
+    type EH = EventHandler<TestTags, usize>;
+
+    /// Handler with a stack of capacity 2 holding T1 and T2
+    fn full_stack(policy: OverflowPolicy<TestTags, usize>) -> (EH, EmRC<usize>) {
+        let mut eh = EH::new();
+        let em = DEm::<TestTags>::new_emrc(None);
+        eh.set_capacity(Some(2));
+        eh.set_overflow_policy(policy);
+        eh.set_dead_letter_sink(DeadLetterSink::Collect);
+        eh.try_emit(em.clone(), T1).unwrap();
+        eh.try_emit(em.clone(), T2).unwrap();
+        (eh, em)
+    }
+
+    #[test]
+    fn rejected_events() {
+        let (mut eh, em) = full_stack(OverflowPolicy::Reject);
+        let full = eh.try_emit(em.clone(), T3).unwrap_err();
+        assert_eq!((full.event.get_tag(), full.capacity, full.missed_eviction), (Some(T3), 2, None));
+        assert_eq!(eh.get_stack_tags(), vec![Some(T1), Some(T2)]);
+        assert_eq!(eh.get_dead_letter_count(), 0);
+
+        // Events pushed without a way to hand them back become dead letters
+        eh.emit(em.clone(), T4(1));
+        let letters = eh.take_dead_letters();
+        assert_eq!(letters.len(), 1);
+        assert_eq!((letters[0].event.get_tag(), letters[0].reason), (Some(T4(1)), DeadLetterReason::Overflow));
+        assert_eq!(letters[0].errors, vec!["Stack of capacity 2 is full".to_string()]);
+        assert_eq!(eh.get_overflow_stats().rejected, 2);
+
+        eh.set_capacity(None);
+        eh.try_emit(em, T3).unwrap();
+        assert_eq!(eh.get_stack_len(), 3);
+    }
+
+    #[test]
+    fn inbox_pushes() {
+        let eh = EHRc::new(RefCell::new(full_stack(OverflowPolicy::Reject).0));
+        let em = DEm::<TestTags>::new_emrc(None);
+        let inbox = Inbox::new(&eh);
+        assert!(!inbox.try_push(Event::new(em.clone(), Some(T3))));
+        inbox.push(Event::new(em.clone(), Some(T3)));
+        assert_eq!(eh.borrow().get_dead_letters()[0].reason, DeadLetterReason::Overflow);
+
+        // Queued events are taken, and only checked once the handler is free
+        {
+            let _dispatching = eh.borrow_mut();
+            assert!(inbox.try_push(Event::new(em.clone(), Some(T3))));
+        }
+        eh.borrow_mut().drain_inbox();
+        assert_eq!(eh.borrow().get_dead_letter_count(), 2);
+        assert_eq!(eh.borrow().get_overflow_stats().rejected, 3);
+    }
+
+    #[test]
+    fn drop_oldest_and_newest() {
+        let (mut eh, em) = full_stack(OverflowPolicy::DropOldest);
+        eh.try_emit(em.clone(), T3).unwrap();
+        assert_eq!(eh.get_stack_tags(), vec![Some(T2), Some(T3)]);
+
+        eh.set_overflow_policy(OverflowPolicy::DropNewest);
+        eh.try_emit(em.clone(), T1).unwrap();
+        assert_eq!(eh.get_stack_tags(), vec![Some(T2), Some(T3)]);
+
+        let stats = eh.get_overflow_stats();
+        assert_eq!((stats.rejected, stats.dropped, stats.evicted, stats.grown), (0, 1, 1, 0));
+        assert_eq!(eh.get_dead_letter_count(), 0);
+    }
+
+    #[test]
+    fn drop_lowest_priority() {
+        let priority = |e: &Event<TestTags, usize>| match e.get_tag() {
+            Some(T4(n)) => n as i64,
+            _ => 0,
+        };
+        let (mut eh, em) = full_stack(OverflowPolicy::DropLowestPriority(Rc::new(priority)));
+        // Among equals, the one pushed first goes
+        eh.try_emit(em.clone(), T4(5)).unwrap();
+        assert_eq!(eh.get_stack_tags(), vec![Some(T2), Some(T4(5))]);
+        eh.try_emit(em.clone(), T4(-1)).unwrap();
+        assert_eq!(eh.get_stack_tags(), vec![Some(T2), Some(T4(5))]);
+
+        let stats = eh.get_overflow_stats();
+        assert_eq!((stats.dropped, stats.evicted), (1, 1));
+    }
+
+    #[test]
+    fn callbacks() {
+        // Grows once, then makes room by dropping the top of the stack
+        let (mut eh, em) = full_stack(OverflowPolicy::Callback(Rc::new(|_, stack| match stack.len() {
+            2 => OverflowAction::Grow,
+            n => OverflowAction::Evict(n - 1),
+        })));
+        eh.push_events(Some(vec![Event::new(em.clone(), Some(T3)), Event::new(em.clone(), Some(T4(1)))]));
+        assert_eq!(eh.get_stack_tags(), vec![Some(T1), Some(T2), Some(T4(1))]);
+
+        // Positions past the top of the stack evict nothing
+        eh.set_overflow_policy(OverflowPolicy::Callback(Rc::new(|_, stack| OverflowAction::Evict(stack.len()))));
+        let full = eh.try_emit(em.clone(), T5("late")).unwrap_err();
+        assert_eq!(full.missed_eviction, Some(3));
+        eh.emit(em, T5("late"));
+        assert_eq!(eh.get_dead_letters()[0].errors, vec!["Stack of capacity 2 is full and has no event at 3 to evict".to_string()]);
+        assert_eq!(eh.get_stack_len(), 3);
+
+        let stats = eh.get_overflow_stats();
+        assert_eq!((stats.rejected, stats.dropped, stats.evicted, stats.grown), (2, 0, 1, 1));
+        assert_eq!(stats.overflows(), 4);
+    }
+}
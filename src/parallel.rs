@@ -0,0 +1,408 @@
+use std::{
+    collections::VecDeque,
+    panic::{self, AssertUnwindSafe},
+    rc::Weak,
+    sync::{mpsc::{self, Sender}, Arc, Mutex},
+    thread::{self, JoinHandle},
+};
+use crate::{prelude::*, event::Event};
+
+/// Results a new dispatcher keeps until they are taken
+pub const DEFAULT_RESULTS_CAPACITY: usize = 256;
+
+/// Listeners a handler in parallel mode hands its broadcast events to, besides its own
+pub(crate) trait ParallelDispatch<T: Tag, I: Id> {
+    /// Hands `event` to the listeners it triggers, `None` if there are none.
+    /// Fails with the listeners' errors if none of them took it.
+    fn dispatch(&mut self, event: &Event<T, I>) -> Option<Result<(), Vec<String>>>;
+}
+
+/// `Send` copy of the parts of an event that can cross threads
+#[derive(Debug, Clone, PartialEq)]
+pub struct SharedEvent<T: Tag, I: Id> {
+    id: usize,
+    cause: Option<usize>,
+    emitter_id: I,
+    tag: Option<T>,
+    depth: usize,
+}
+
+impl<T: Tag, I: Id> From<&Event<T, I>> for SharedEvent<T, I> {
+    fn from(event: &Event<T, I>) -> Self {
+        Self {
+            id: event.get_id(),
+            cause: event.get_cause(),
+            emitter_id: event.get_emitter().borrow().get_id(),
+            tag: event.get_tag(),
+            depth: event.get_depth(),
+        }
+    }
+}
+
+impl<T: Tag, I: Id> SharedEvent<T, I> {
+    pub fn get_id(&self) -> usize {
+        self.id
+    }
+    pub fn get_cause(&self) -> Option<usize> {
+        self.cause
+    }
+    pub fn get_emitter_id(&self) -> &I {
+        &self.emitter_id
+    }
+    pub fn get_tag(&self) -> Option<T> {
+        self.tag
+    }
+    pub fn get_depth(&self) -> usize {
+        self.depth
+    }
+}
+
+/// Listener that can handle events on any thread, at the same time as other listeners
+pub trait ParallelListener<T: Tag, I: Id>: Send + Sync {
+    fn get_id(&self) -> I;
+    fn has_trigger(&self, tag: &T) -> bool;
+    fn on_event(&self, event: &SharedEvent<T, I>) -> Result<(), String>;
+}
+
+/// Outcome of handing one event to one listener
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListenerResult<I: Id> {
+    pub event_id: usize,
+    pub listener_id: I,
+    /// Listeners that panic count as failed
+    pub result: Result<(), String>,
+}
+
+type Job = Box<dyn FnOnce() + Send>;
+
+struct Pool {
+    sender: Option<Sender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl Pool {
+    fn new(threads: usize) -> Self {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers = (0..threads.max(1)).map(|_| {
+            let receiver = receiver.clone();
+            thread::spawn(move || loop {
+                let job = match receiver.lock() {
+                    Ok(receiver) => receiver.recv(),
+                    Err(_) => break,
+                };
+                match job {
+                    Ok(job) => job(),
+                    Err(_) => break,
+                }
+            })
+        }).collect();
+        Self { sender: Some(sender), workers }
+    }
+    /// Hands `job` to the workers, fails if every one of them is gone
+    fn run(&self, job: Job) -> Result<(), String> {
+        self.sender.as_ref()
+            .and_then(|sender| sender.send(job).ok())
+            .ok_or_else(|| "The worker pool is gone".to_string())
+    }
+}
+
+impl Drop for Pool {
+    fn drop(&mut self) {
+        self.sender.take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+type ParallelLiRC<T, I> = Arc<dyn ParallelListener<T, I>>;
+
+struct DispatcherState<T: Tag, I: Id> {
+    pool: Pool,
+    listeners: Vec<ParallelLiRC<T, I>>,
+    results: VecDeque<ListenerResult<I>>,
+    results_capacity: usize,
+    errors: usize,
+}
+
+impl<T: Tag + Send + Sync, I: Id + Send + Sync> ParallelDispatch<T, I> for DispatcherState<T, I> {
+    fn dispatch(&mut self, event: &Event<T, I>) -> Option<Result<(), Vec<String>>> {
+        let tag = event.get_tag()?;
+        if event.is_reply() {
+            return None;
+        }
+        let targets: Vec<ParallelLiRC<T, I>> = self.listeners.iter().filter(|li| li.has_trigger(&tag)).cloned().collect();
+        if targets.is_empty() {
+            return None;
+        }
+        let shared = Arc::new(SharedEvent::from(event));
+
+        let (sender, receiver) = mpsc::channel();
+        let mut results: Vec<Option<Result<(), String>>> = vec![None; targets.len()];
+        for (pos, li) in targets.iter().enumerate() {
+            let (li, shared, sender) = (li.clone(), shared.clone(), sender.clone());
+            let job: Job = Box::new(move || {
+                let result = panic::catch_unwind(AssertUnwindSafe(|| li.on_event(&shared)))
+                    .unwrap_or_else(|_| Err(format!("Listener {:?} panicked", li.get_id())));
+                let _ = sender.send((pos, result));
+            });
+            if let Err(e) = self.pool.run(job) {
+                results[pos] = Some(Err(e));
+            }
+        }
+        drop(sender);
+
+        // Waits for every listener, then keeps the results in listener order
+        for (pos, result) in receiver.iter() {
+            results[pos] = Some(result);
+        }
+        let mut errors = vec![];
+        for (pos, result) in results.into_iter().enumerate() {
+            // Jobs dropped by a dying worker never send their result
+            let result = result.unwrap_or_else(|| Err(format!("Listener {:?} got no worker", targets[pos].get_id())));
+            if let Err(e) = &result {
+                self.errors += 1;
+                errors.push(e.clone());
+            }
+            if self.results_capacity > 0 {
+                if self.results.len() == self.results_capacity {
+                    self.results.pop_front();
+                }
+                self.results.push_back(ListenerResult { event_id: event.get_id(), listener_id: targets[pos].get_id(), result });
+            }
+        }
+        if errors.len() < targets.len() { Some(Ok(())) } else { Some(Err(errors)) }
+    }
+}
+
+/// Puts an `EventHandler` in parallel mode, handing every event it broadcasts to the
+/// dispatcher's `ParallelListener`s, all at once on a pool of worker threads.
+///
+/// The handler waits until every listener is done with an event before going on,
+/// so each listener gets the events one at a time, in the order they were broadcast.
+/// Events taken by a parallel listener are no dead letters, and those failed by every
+/// listener carry the parallel listeners' errors too. Should the workers be gone,
+/// the listeners fail the events instead of being handed them. Parallel mode ends
+/// once the dispatcher is dropped.
+pub struct ParallelDispatcher<T: Tag, I: Id> {
+    state: Rc<RefCell<DispatcherState<T, I>>>,
+}
+
+impl<T: Tag, I: Id> Debug for ParallelDispatcher<T, I> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let state = self.state.borrow();
+        f.debug_struct("ParallelDispatcher")
+            .field("threads", &state.pool.workers.len())
+            .field("listener ids", &state.listeners.iter().map(|li| li.get_id()).collect::<Vec<I>>())
+            .field("errors", &state.errors)
+            .finish()
+    }
+}
+
+impl<T: Tag + Send + Sync, I: Id + Send + Sync> ParallelDispatcher<T, I> {
+    /// Dispatcher with `threads` workers, at least one, replacing the one `eh` had if any
+    pub fn new(eh: &EHRc<T, I>, threads: usize) -> Self {
+        let state = Rc::new(RefCell::new(DispatcherState {
+            pool: Pool::new(threads),
+            listeners: Vec::new(),
+            results: VecDeque::new(),
+            results_capacity: DEFAULT_RESULTS_CAPACITY,
+            errors: 0,
+        }));
+        let weak: Weak<RefCell<DispatcherState<T, I>>> = Rc::downgrade(&state);
+        eh.borrow_mut().set_parallel_dispatch(Some(weak));
+        Self { state }
+    }
+    pub fn add_listener(&mut self, listener: impl ParallelListener<T, I> + 'static) -> Result<(), String> {
+        let mut state = self.state.borrow_mut();
+        let id = listener.get_id();
+        if state.listeners.iter().any(|li| li.get_id() == id) {
+            return Err(format!("ParallelDispatcher already has listener {:?}", id));
+        }
+        state.listeners.push(Arc::new(listener));
+        Ok(())
+    }
+    pub fn remove_listener(&mut self, listener_id: &I) -> bool {
+        let mut state = self.state.borrow_mut();
+        let len = state.listeners.len();
+        state.listeners.retain(|li| li.get_id() != *listener_id);
+        state.listeners.len() != len
+    }
+    pub fn get_thread_count(&self) -> usize {
+        self.state.borrow().pool.workers.len()
+    }
+    pub fn get_results(&self) -> Vec<ListenerResult<I>> {
+        self.state.borrow().results.iter().cloned().collect()
+    }
+    pub fn take_results(&mut self) -> Vec<ListenerResult<I>> {
+        std::mem::take(&mut self.state.borrow_mut().results).into()
+    }
+    /// Keeps the last `capacity` results until they are taken, a capacity of 0 keeps none.
+    /// Defaults to `DEFAULT_RESULTS_CAPACITY`.
+    pub fn set_results_capacity(&mut self, capacity: usize) {
+        let mut state = self.state.borrow_mut();
+        state.results_capacity = capacity;
+        let excess = state.results.len().saturating_sub(capacity);
+        state.results.drain(..excess);
+    }
+    /// Failed deliveries so far, including the ones whose results were taken
+    pub fn get_error_count(&self) -> usize {
+        self.state.borrow().errors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Barrier, time::Duration};
+    use crate::{
+        def_emitter::DefEmitter as DEm,
+        dead_letter::{DeadLetterReason, DeadLetterSink},
+        event_handler::EventHandler,
+        tests::TestTags::{self, *},
+    };
+    use super::*;
+
+    /// Takes T4 and T5, failing T5 if its id is even and panicking otherwise
+    struct Analytics {
+        id: usize,
+        /// Waited on before handling each event
+        barrier: Option<Arc<Barrier>>,
+        seen: Mutex<Vec<Option<TestTags>>>,
+    }
+
+    impl ParallelListener<TestTags, usize> for Arc<Analytics> {
+        fn get_id(&self) -> usize {
+            self.id
+        }
+        fn has_trigger(&self, tag: &TestTags) -> bool {
+            matches!(tag, T4(_) | T5(_))
+        }
+        fn on_event(&self, event: &SharedEvent<TestTags, usize>) -> Result<(), String> {
+            if let Some(barrier) = &self.barrier {
+                barrier.wait();
+            }
+            // Listeners take their time, unevenly, so their events could overtake each other
+            if let Some(T4(n)) = event.get_tag() {
+                thread::sleep(Duration::from_millis(((n as usize * 7 + self.id * 3) % 5) as u64));
+            }
+            self.seen.lock().unwrap().push(event.get_tag());
+            match event.get_tag() {
+                Some(T5(e)) if self.id.is_multiple_of(2) => Err(e.to_string()),
+                Some(T5(e)) => panic!("{}", e),
+                _ => Ok(()),
+            }
+        }
+    }
+
+    type Setup = (EHRc<TestTags, usize>, ParallelDispatcher<TestTags, usize>, Vec<Arc<Analytics>>);
+
+    fn dispatcher(threads: usize, listeners: usize, barrier: Option<Arc<Barrier>>) -> Setup {
+        let eh = EventHandler::new_ehrc();
+        eh.borrow_mut().set_dead_letter_sink(DeadLetterSink::Collect);
+        let mut dispatcher = ParallelDispatcher::new(&eh, threads);
+        let listeners: Vec<Arc<Analytics>> = (0..listeners)
+            .map(|id| Arc::new(Analytics { id, barrier: barrier.clone(), seen: Mutex::new(vec![]) }))
+            .collect();
+        for li in &listeners {
+            dispatcher.add_listener(li.clone()).unwrap();
+        }
+        (eh, dispatcher, listeners)
+    }
+
+    fn run(eh: &EHRc<TestTags, usize>, tags: impl IntoIterator<Item = TestTags>) {
+        let em = DEm::<TestTags>::new_emrc(None);
+        for tag in tags {
+            eh.borrow_mut().emit(em.clone(), tag);
+            eh.borrow_mut().consume_next_event();
+        }
+    }
+
+    #[test]
+    fn listeners_run_at_the_same_time() {
+        // Every listener waits for all of the others, so none is done unless they all run at once
+        let (eh, mut dispatcher, listeners) = dispatcher(4, 4, Some(Arc::new(Barrier::new(4))));
+        assert!(eh.borrow().is_parallel_mode());
+        assert_eq!(dispatcher.get_thread_count(), 4);
+        assert!(dispatcher.add_listener(listeners[0].clone()).is_err());
+
+        run(&eh, [T4(1), T4(2)]);
+        for li in &listeners {
+            assert_eq!(*li.seen.lock().unwrap(), vec![Some(T4(1)), Some(T4(2))]);
+        }
+        assert_eq!(dispatcher.get_error_count(), 0);
+    }
+
+    #[test]
+    fn listeners_get_events_in_order() {
+        let (eh, _dispatcher, listeners) = dispatcher(3, 6, None);
+        let tags: Vec<TestTags> = (0..20).map(T4).collect();
+        run(&eh, tags.clone());
+
+        let expected: Vec<Option<TestTags>> = tags.into_iter().map(Some).collect();
+        for li in &listeners {
+            assert_eq!(*li.seen.lock().unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn results_and_errors() {
+        let (eh, mut dispatcher, _) = dispatcher(4, 4, None);
+        run(&eh, [T4(1), T5("bad input")]);
+
+        let results = dispatcher.take_results();
+        assert_eq!(results.len(), 8);
+        assert_eq!(results.iter().map(|r| r.listener_id).take(4).collect::<Vec<usize>>(), vec![0, 1, 2, 3]);
+        assert!(results[..4].iter().all(|r| r.result.is_ok()));
+        assert_eq!(results[4].result, Err("bad input".to_string()));
+        assert_eq!(results[5].result, Err("Listener 1 panicked".to_string()));
+        assert_eq!(dispatcher.get_error_count(), 4);
+        assert!(dispatcher.get_results().is_empty());
+
+        // Only the last results are kept
+        dispatcher.set_results_capacity(2);
+        run(&eh, [T4(3)]);
+        assert_eq!(dispatcher.get_results().iter().map(|r| r.listener_id).collect::<Vec<usize>>(), vec![2, 3]);
+    }
+
+    #[test]
+    fn dead_letters() {
+        let (eh, _dispatcher, _) = dispatcher(2, 2, None);
+        run(&eh, [T1, T4(1), T5("bad input")]);
+
+        // Only events no parallel listener took are dead letters
+        let letters = eh.borrow_mut().take_dead_letters();
+        let reasons: Vec<_> = letters.iter().map(|l| (l.event.get_tag(), l.reason, l.errors.len())).collect();
+        assert_eq!(reasons, vec![(Some(T1), DeadLetterReason::NoListeners, 0), (Some(T5("bad input")), DeadLetterReason::AllFailed, 2)]);
+    }
+
+    #[test]
+    fn dead_pool() {
+        let (eh, dispatcher, listeners) = dispatcher(2, 2, None);
+        // A pool whose workers all stopped
+        let (sender, _) = mpsc::channel::<Job>();
+        dispatcher.state.borrow_mut().pool = Pool { sender: Some(sender), workers: vec![] };
+
+        run(&eh, [T4(1)]);
+        assert!(listeners.iter().all(|li| li.seen.lock().unwrap().is_empty()));
+        assert_eq!(dispatcher.get_error_count(), 2);
+        let letters = eh.borrow_mut().take_dead_letters();
+        assert_eq!(letters[0].reason, DeadLetterReason::AllFailed);
+        assert_eq!(letters[0].errors, vec!["The worker pool is gone".to_string(); 2]);
+    }
+
+    #[test]
+    fn dropping_the_dispatcher() {
+        let (eh, mut dispatcher, listeners) = dispatcher(2, 2, None);
+        assert!(dispatcher.remove_listener(&0));
+        assert!(!dispatcher.remove_listener(&0));
+        run(&eh, [T4(1)]);
+        assert_eq!(listeners[0].seen.lock().unwrap().len(), 0);
+        assert_eq!(listeners[1].seen.lock().unwrap().len(), 1);
+
+        drop(dispatcher);
+        assert!(!eh.borrow().is_parallel_mode());
+        run(&eh, [T4(2)]);
+        assert_eq!(listeners[1].seen.lock().unwrap().len(), 1);
+    }
+}
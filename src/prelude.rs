@@ -5,4 +5,5 @@ pub use crate::{
     event_handler::EHRc,
     eh_parent::EHParent,
     listener::{IListener, LiRC},
+    inbox::Inbox,
 };
\ No newline at end of file
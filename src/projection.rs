@@ -0,0 +1,313 @@
+use std::{any::Any, collections::VecDeque, rc::Weak};
+use crate::{prelude::*, event::Event, event_handler::{EventHandler, ObserverHandle}};
+
+/// Consumed events a new projection keeps in its log
+pub const DEFAULT_LOG_CAPACITY: usize = 1024;
+
+/// State derived by folding events, one at a time and in the order they were consumed
+pub trait Aggregate<T: Tag, I: Id> {
+    fn apply(&mut self, event: &Event<T, I>);
+}
+
+/// Aggregate of any type registered with a `Projection`
+trait Slot<T: Tag, I: Id> {
+    fn apply(&mut self, event: &Event<T, I>);
+    /// Folds an event leaving the log into the state the log starts from
+    fn compact(&mut self, event: &Event<T, I>);
+    /// Goes back to the state the log starts from
+    fn reset(&mut self);
+    /// Goes back to the initial state, for a log starting from scratch
+    fn restart(&mut self);
+    fn as_any(&self) -> &dyn Any;
+}
+
+struct TypedSlot<A> {
+    initial: A,
+    /// State before the first logged event
+    base: A,
+    current: Rc<RefCell<A>>,
+}
+
+impl<T: Tag, I: Id, A: Aggregate<T, I> + Clone + 'static> Slot<T, I> for TypedSlot<A> {
+    fn apply(&mut self, event: &Event<T, I>) {
+        self.current.borrow_mut().apply(event);
+    }
+    fn compact(&mut self, event: &Event<T, I>) {
+        self.base.apply(event);
+    }
+    fn reset(&mut self) {
+        *self.current.borrow_mut() = self.base.clone();
+    }
+    fn restart(&mut self) {
+        self.base = self.initial.clone();
+        self.reset();
+    }
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+struct ProjectionState<T: Tag, I: Id> {
+    log: VecDeque<Event<T, I>>,
+    log_capacity: usize,
+    /// Sequence number of the last folded event, which is how many were folded
+    sequence: usize,
+    slots: Vec<Box<dyn Slot<T, I>>>,
+    paused: bool,
+}
+
+impl<T: Tag, I: Id> ProjectionState<T, I> {
+    fn fold(&mut self, event: &Event<T, I>) {
+        if self.paused {
+            return;
+        }
+        self.sequence += 1;
+        self.log.push_back(event.clone());
+        for slot in self.slots.iter_mut() {
+            slot.apply(event);
+        }
+        self.compact();
+    }
+    /// Sequence number of the state the log starts from
+    fn get_base_sequence(&self) -> usize {
+        self.sequence - self.log.len()
+    }
+    fn compact(&mut self) {
+        while self.log.len() > self.log_capacity {
+            let Some(e) = self.log.pop_front() else { break };
+            for slot in self.slots.iter_mut() {
+                slot.compact(&e);
+            }
+        }
+    }
+}
+
+/// Folds every event consumed by an `EventHandler` into its aggregates.
+///
+/// Folded events are numbered from 1 and the last ones are kept in a log, which
+/// aggregates are rebuilt from and snapshots are taken at: the snapshot at sequence
+/// number `n` is the state after the first `n` events. Events leaving the log are
+/// folded into the state it starts from, so a capped log never changes the aggregates.
+/// Folding stops once the projection is dropped.
+pub struct Projection<T: Tag, I: Id> {
+    state: Rc<RefCell<ProjectionState<T, I>>>,
+    _observer: ObserverHandle<T, I>,
+}
+
+impl<T: Tag, I: Id> Debug for Projection<T, I> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let state = self.state.borrow();
+        f.debug_struct("Projection")
+            .field("sequence", &state.sequence)
+            .field("logged", &state.log.len())
+            .field("aggregates", &state.slots.len())
+            .field("paused", &state.paused)
+            .finish()
+    }
+}
+
+impl<T: Tag, I: Id> Projection<T, I> {
+    pub fn new(eh: &EHRc<T, I>) -> Self {
+        let state = Rc::new(RefCell::new(ProjectionState {
+            log: VecDeque::new(),
+            log_capacity: DEFAULT_LOG_CAPACITY,
+            sequence: 0,
+            slots: Vec::new(),
+            paused: false,
+        }));
+        let weak: Weak<RefCell<ProjectionState<T, I>>> = Rc::downgrade(&state);
+        let observer = EventHandler::observe(eh, move |e| {
+            if let Some(state) = weak.upgrade() {
+                state.borrow_mut().fold(e);
+            }
+        });
+        Self { state, _observer: observer }
+    }
+    /// Registers an aggregate starting from `initial`, caught up with the events still logged.
+    /// The returned handle always holds the latest state.
+    pub fn add_aggregate<A: Aggregate<T, I> + Clone + 'static>(&mut self, initial: A) -> Rc<RefCell<A>> {
+        let mut current = initial.clone();
+        let mut state = self.state.borrow_mut();
+        for e in &state.log {
+            current.apply(e);
+        }
+        let current = Rc::new(RefCell::new(current));
+        state.slots.push(Box::new(TypedSlot { base: initial.clone(), initial, current: current.clone() }));
+        current
+    }
+    /// Keeps the last `capacity` folded events in the log, snapshots can no longer be
+    /// taken before the first of them. Defaults to `DEFAULT_LOG_CAPACITY`.
+    pub fn set_log_capacity(&mut self, capacity: usize) {
+        let mut state = self.state.borrow_mut();
+        state.log_capacity = capacity;
+        state.compact();
+    }
+    /// Sequence number of the last folded event, 0 before any
+    pub fn get_sequence(&self) -> usize {
+        self.state.borrow().sequence
+    }
+    pub fn get_log(&self) -> Vec<Event<T, I>> {
+        self.state.borrow().log.iter().cloned().collect()
+    }
+    /// Stops folding consumed events until `resume`, they are not logged either
+    pub fn pause(&mut self) {
+        self.state.borrow_mut().paused = true;
+    }
+    pub fn resume(&mut self) {
+        self.state.borrow_mut().paused = false;
+    }
+    /// State `aggregate` was in at sequence number `seq`, `None` if `seq` is past the
+    /// last folded event or before the log, or if `aggregate` is not from this projection
+    pub fn snapshot_at<A: Aggregate<T, I> + Clone + 'static>(&self, aggregate: &Rc<RefCell<A>>, seq: usize) -> Option<A> {
+        let state = self.state.borrow();
+        let logged = seq.checked_sub(state.get_base_sequence()).filter(|n| *n <= state.log.len())?;
+        let slot = state.slots.iter()
+            .filter_map(|s| s.as_any().downcast_ref::<TypedSlot<A>>())
+            .find(|s| Rc::ptr_eq(&s.current, aggregate))?;
+
+        let mut snapshot = slot.base.clone();
+        for e in state.log.range(..logged) {
+            snapshot.apply(e);
+        }
+        Some(snapshot)
+    }
+    /// Resets every aggregate to its initial state and folds `log` into them,
+    /// which replaces the logged events and numbers them from 1 again
+    pub fn rebuild_from(&mut self, log: Vec<Event<T, I>>) {
+        let mut state = self.state.borrow_mut();
+        state.sequence = log.len();
+        state.log = log.into();
+        for slot in state.slots.iter_mut() {
+            slot.restart();
+        }
+        state.compact();
+        let ProjectionState { log, slots, .. } = &mut *state;
+        for slot in slots.iter_mut() {
+            slot.reset();
+            for e in log.iter() {
+                slot.apply(e);
+            }
+        }
+
+        #[cfg(test)]
+        println!("Projection rebuilt {} aggregates from {} events", slots.len(), log.len());
+    }
+    /// Resets every aggregate to the state the log starts from and folds the logged events into them again
+    pub fn rebuild(&mut self) {
+        let mut state = self.state.borrow_mut();
+        let ProjectionState { log, slots, .. } = &mut *state;
+        for slot in slots.iter_mut() {
+            slot.reset();
+            for e in log.iter() {
+                slot.apply(e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{def_emitter::DefEmitter as DEm, tests::TestTags::{self, *}};
+    use super::*;
+
+    #[derive(Debug, Clone, Default, PartialEq)]
+    struct Balance {
+        total: i32,
+        moves: usize,
+    }
+
+    impl Aggregate<TestTags, usize> for Balance {
+        fn apply(&mut self, event: &Event<TestTags, usize>) {
+            if let Some(T4(n)) = event.get_tag() {
+                self.total += n;
+                self.moves += 1;
+            }
+        }
+    }
+
+    #[derive(Clone)]
+    struct Resets(usize);
+
+    impl Aggregate<TestTags, usize> for Resets {
+        fn apply(&mut self, event: &Event<TestTags, usize>) {
+            if event.get_tag() == Some(T1) {
+                self.0 += 1;
+            }
+        }
+    }
+
+    fn consume(eh: &EHRc<TestTags, usize>, tags: &[TestTags]) {
+        let em = DEm::<TestTags>::new_emrc(None);
+        for tag in tags {
+            eh.borrow_mut().emit(em.clone(), *tag);
+            eh.borrow_mut().consume_next_event();
+        }
+    }
+
+    #[test]
+    fn aggregates_fold_consumed_events() {
+        let eh = EventHandler::<TestTags, usize>::new_ehrc();
+        let mut projection = Projection::new(&eh);
+        let balance = projection.add_aggregate(Balance::default());
+        consume(&eh, &[T4(2), T4(3), T1]);
+        assert_eq!(*balance.borrow(), Balance { total: 5, moves: 2 });
+        // Late aggregates catch up with the log
+        let resets = projection.add_aggregate(Resets(0));
+        assert_eq!(resets.borrow().0, 1);
+
+        projection.pause();
+        consume(&eh, &[T4(10)]);
+        projection.resume();
+        assert_eq!(balance.borrow().total, 5);
+        assert_eq!(projection.get_sequence(), 3);
+
+        drop(projection);
+        assert_eq!(eh.borrow().get_observer_count(), 0);
+    }
+
+    #[test]
+    fn snapshots() {
+        let eh = EventHandler::<TestTags, usize>::new_ehrc();
+        let mut projection = Projection::new(&eh);
+        let balance = projection.add_aggregate(Balance::default());
+        assert_eq!(projection.get_sequence(), 0);
+        consume(&eh, &[T4(2), T4(3), T1]);
+
+        assert_eq!(projection.get_sequence(), 3);
+        assert_eq!(projection.snapshot_at(&balance, 0), Some(Balance::default()));
+        assert_eq!(projection.snapshot_at(&balance, 1), Some(Balance { total: 2, moves: 1 }));
+        assert_eq!(projection.snapshot_at(&balance, 3), Some(Balance { total: 5, moves: 2 }));
+        assert_eq!(projection.snapshot_at(&balance, 4), None);
+        assert_eq!(projection.snapshot_at(&Rc::new(RefCell::new(Balance::default())), 1), None);
+
+        // Only from the start of a capped log
+        projection.set_log_capacity(1);
+        assert_eq!(projection.snapshot_at(&balance, 1), None);
+        assert_eq!(projection.snapshot_at(&balance, 2), Some(Balance { total: 5, moves: 2 }));
+        assert_eq!(projection.snapshot_at(&balance, 3), Some(Balance { total: 5, moves: 2 }));
+    }
+
+    #[test]
+    fn rebuilds() {
+        let eh = EventHandler::<TestTags, usize>::new_ehrc();
+        let mut projection = Projection::new(&eh);
+        let balance = projection.add_aggregate(Balance::default());
+        let resets = projection.add_aggregate(Resets(0));
+        consume(&eh, &[T4(2), T4(3), T1]);
+
+        let log = projection.get_log().into_iter().skip(1).collect();
+        projection.rebuild_from(log);
+        assert_eq!(*balance.borrow(), Balance { total: 3, moves: 1 });
+        assert_eq!(resets.borrow().0, 1);
+        assert_eq!(projection.get_sequence(), 2);
+
+        // Events leaving a capped log stay folded into the aggregates
+        projection.set_log_capacity(1);
+        assert_eq!(projection.get_log().len(), 1);
+        consume(&eh, &[T4(4)]);
+        projection.rebuild();
+        assert_eq!(*balance.borrow(), Balance { total: 7, moves: 2 });
+        assert_eq!(resets.borrow().0, 1);
+        assert_eq!(projection.get_sequence(), 3);
+    }
+}
@@ -0,0 +1,198 @@
+use crate::{prelude::*, event::Event, IDCOUNTER};
+
+type Transform<T> = Rc<dyn Fn(&Event<T, usize>) -> Option<T>>;
+
+/// Listener that emits, as itself, a transformed event for every event it is triggered by.
+///
+/// Transformed events are pushed through an `Inbox` into every connected handler,
+/// including the one the relay listens on, and are caused by the event they come from.
+/// Clones share their connections.
+#[derive(Clone)]
+pub struct Relay<T: Tag> {
+    id: usize,
+    triggers: Vec<T>,
+    transform: Transform<T>,
+    outputs: Rc<RefCell<Vec<Inbox<T, usize>>>>,
+}
+
+impl<T: Tag> Debug for Relay<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Relay")
+            .field("id", &self.id)
+            .field("triggers", &self.triggers)
+            .field("output ids", &self.get_connected_handlers())
+            .finish()
+    }
+}
+
+impl<T: Tag> EmitObj<usize> for Relay<T> {
+    fn get_id(&self) -> usize {
+        self.id
+    }
+}
+
+impl<T: Tag> Relay<T> {
+    /// `transform` gives the tag to emit for an event, `None` to emit nothing
+    pub fn new(triggers: Vec<T>, transform: impl Fn(&Event<T, usize>) -> Option<T> + 'static) -> Self {
+        Self {
+            id: IDCOUNTER.fetch_add(1, std::sync::atomic::Ordering::SeqCst),
+            triggers,
+            transform: Rc::new(transform),
+            outputs: Rc::new(RefCell::new(Vec::new())),
+        }
+    }
+    pub fn new_lirc(triggers: Vec<T>, transform: impl Fn(&Event<T, usize>) -> Option<T> + 'static) -> LiRC<T, usize> {
+        LiRC(Rc::new(RefCell::new(Self::new(triggers, transform))))
+    }
+    pub fn connect(&mut self, eh: &EHRc<T, usize>) {
+        let inbox = Inbox::new(eh);
+        let mut outputs = self.outputs.borrow_mut();
+        if !outputs.contains(&inbox) {
+            outputs.push(inbox);
+        }
+    }
+    pub fn disconnect(&mut self, eh: &EHRc<T, usize>) -> bool {
+        let id = eh.borrow().get_id();
+        let mut outputs = self.outputs.borrow_mut();
+        let len = outputs.len();
+        outputs.retain(|i| i.get_handler_id() != id);
+        outputs.len() != len
+    }
+    /// Ids of the handlers the relay emits into, dropped handlers excluded
+    pub fn get_connected_handlers(&self) -> Vec<usize> {
+        self.outputs.borrow().iter().filter(|i| i.is_connected()).map(|i| i.get_handler_id()).collect()
+    }
+}
+
+impl<T: Tag> IListener<T, usize> for Relay<T> {
+    fn get_triggers(&self) -> Vec<&T> {
+        self.triggers.iter().collect()
+    }
+    fn has_trigger(&self, tag: &T) -> bool {
+        self.triggers.contains(tag)
+    }
+    fn on_triggers(&self, triggers: Vec<Event<T, usize>>) {
+        let emitter = self.as_emrc();
+        for e in &triggers {
+            let Some(tag) = (self.transform)(e) else { continue };
+
+            #[cfg(test)]
+            println!("Relay_{} turned {:?} into {:?}", self.id, e.get_tag(), tag);
+
+            for inbox in self.outputs.borrow().iter() {
+                inbox.push(Event::new(emitter.clone(), Some(tag)).with_cause(e.get_id()));
+            }
+        }
+    }
+    fn as_lirc(&self) -> LiRC<T, usize> {
+        LiRC(Rc::new(RefCell::new(self.clone())))
+    }
+    fn into_lirc(self) -> Result<LiRC<T, usize>, &'static str> {
+        Ok(LiRC(Rc::new(RefCell::new(self))))
+    }
+    fn try_into_lirc(self) -> Option<LiRC<T, usize>> {
+        Some(LiRC(Rc::new(RefCell::new(self))))
+    }
+    fn as_emrc(&self) -> EmRC<usize> {
+        EmRC(Rc::new(RefCell::new(self.clone())))
+    }
+    fn into_emrc(self) -> EmRC<usize> {
+        EmRC(Rc::new(RefCell::new(self)))
+    }
+}
+
+/// Chain of `Relay` stages, each listening on the handler the previous stage emits into
+#[derive(Debug)]
+pub struct Pipeline<T: Tag> {
+    handler: EHRc<T, usize>,
+    stages: Vec<Relay<T>>,
+}
+
+impl<T: Tag> Pipeline<T> {
+    /// Pipeline whose first stage listens on `eh`
+    pub fn new(eh: &EHRc<T, usize>) -> Self {
+        Self { handler: eh.clone(), stages: Vec::new() }
+    }
+    /// Adds a stage emitting back into the handler it listens on
+    pub fn stage(self, triggers: Vec<T>, transform: impl Fn(&Event<T, usize>) -> Option<T> + 'static) -> Result<Self, String> {
+        let eh = self.handler.clone();
+        self.stage_into(&eh, triggers, transform)
+    }
+    /// Adds a stage emitting into `to`, which the next stages listen on
+    pub fn stage_into(mut self, to: &EHRc<T, usize>, triggers: Vec<T>, transform: impl Fn(&Event<T, usize>) -> Option<T> + 'static) -> Result<Self, String> {
+        let mut relay = Relay::new(triggers, transform);
+        relay.connect(to);
+        self.handler.borrow_mut().add_listener(relay.as_lirc())?;
+        self.stages.push(relay);
+        self.handler = to.clone();
+        Ok(self)
+    }
+    pub fn get_stages(&self) -> &Vec<Relay<T>> {
+        &self.stages
+    }
+    /// Handler the last stage emits into
+    pub fn get_output(&self) -> &EHRc<T, usize> {
+        &self.handler
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{def_emitter::DefEmitter as DEm, event_handler::EventHandler, tests::{TestTags::{self, *}, Recorder}};
+    use super::*;
+
+    type EH = EventHandler<TestTags, usize>;
+
+    #[test]
+    fn relays_emit_caused_events() {
+        let (input, output) = (EH::new_ehrc(), EH::new_ehrc());
+        let em = DEm::<TestTags>::new_emrc(None);
+        let recorder = Recorder::new(vec![T4(10)], None);
+        output.borrow_mut().add_listener(recorder.as_lirc()).unwrap();
+
+        let mut tens = Relay::new(vec![T4(1), T4(2)], |e| match e.get_tag() {
+            Some(T4(1)) => Some(T4(10)),
+            _ => None,
+        });
+        input.borrow_mut().add_listener(tens.as_lirc()).unwrap();
+        tens.connect(&output);
+        tens.connect(&output);
+        assert_eq!(tens.get_connected_handlers(), vec![output.borrow().get_id()]);
+
+        for tag in [T4(1), T4(2)] {
+            input.borrow_mut().emit(em.clone(), tag);
+        }
+        input.borrow_mut().run_until_empty(Default::default());
+        output.borrow_mut().run_until_empty(Default::default());
+
+        assert_eq!(recorder.received_tags(), vec![Some(T4(10))]);
+        let received = recorder.received.borrow();
+        assert_eq!(received[0].get_emitter(), tens.as_emrc());
+        assert!(received[0].get_cause().is_some());
+
+        assert!(tens.disconnect(&output));
+        assert_eq!(tens.get_connected_handlers(), vec![]);
+    }
+
+    #[test]
+    fn pipeline_stages() {
+        let (input, output) = (EH::new_ehrc(), EH::new_ehrc());
+        let em = DEm::<TestTags>::new_emrc(None);
+        let recorder = Recorder::new(vec![T3], None);
+        output.borrow_mut().add_listener(recorder.as_lirc()).unwrap();
+
+        // T1 becomes T2 on input, which becomes T3 on output
+        let pipeline = Pipeline::new(&input)
+            .stage(vec![T1], |_| Some(T2)).unwrap()
+            .stage_into(&output, vec![T2], |_| Some(T3)).unwrap();
+        assert_eq!(pipeline.get_stages().len(), 2);
+        assert_eq!(pipeline.get_output(), &output);
+
+        input.borrow_mut().emit(em, T1);
+        input.borrow_mut().run_until_empty(Default::default());
+        output.borrow_mut().run_until_empty(Default::default());
+
+        assert_eq!(recorder.received_tags(), vec![Some(T3)]);
+        assert_eq!(recorder.received.borrow()[0].get_emitter(), pipeline.get_stages()[1].as_emrc());
+    }
+}
@@ -0,0 +1,310 @@
+use std::rc::Weak;
+use crate::{prelude::*, event::Event, event_handler::{EventHandler, ObserverHandle}};
+
+type Predicate<T, I> = Rc<dyn Fn(&Event<T, I>) -> bool>;
+type TagMap<T> = Rc<dyn Fn(T) -> T>;
+
+/// Which consumed events a `Route` forwards
+#[derive(Clone)]
+pub enum RouteMatch<T: Tag, I: Id> {
+    Any,
+    Tag(T),
+    Emitter(I),
+    Predicate(Predicate<T, I>),
+}
+
+impl<T: Tag, I: Id> Debug for RouteMatch<T, I> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RouteMatch::Any => write!(f, "Any"),
+            RouteMatch::Tag(t) => write!(f, "Tag({:?})", t),
+            RouteMatch::Emitter(id) => write!(f, "Emitter({:?})", id),
+            RouteMatch::Predicate(_) => write!(f, "Predicate"),
+        }
+    }
+}
+
+impl<T: Tag, I: Id> RouteMatch<T, I> {
+    pub fn matches(&self, event: &Event<T, I>) -> bool {
+        match self {
+            RouteMatch::Any => true,
+            RouteMatch::Tag(t) => event.get_tag() == Some(*t),
+            RouteMatch::Emitter(id) => event.get_emitter().borrow().get_id() == *id,
+            RouteMatch::Predicate(p) => p(event),
+        }
+    }
+}
+
+/// Forwards the events consumed by one handler into another
+#[derive(Clone)]
+pub struct Route<T: Tag, I: Id> {
+    from: Inbox<T, I>,
+    to: Inbox<T, I>,
+    matcher: RouteMatch<T, I>,
+    map_tag: Option<TagMap<T>>,
+}
+
+impl<T: Tag, I: Id> Debug for Route<T, I> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Route")
+            .field("from", &self.from.get_handler_id())
+            .field("to", &self.to.get_handler_id())
+            .field("matcher", &self.matcher)
+            .field("maps tag", &self.map_tag.is_some())
+            .finish()
+    }
+}
+
+impl<T: Tag, I: Id> Route<T, I> {
+    pub fn new(from: &EHRc<T, I>, to: &EHRc<T, I>, matcher: RouteMatch<T, I>) -> Self {
+        Self { from: Inbox::new(from), to: Inbox::new(to), matcher, map_tag: None }
+    }
+    /// Changes the tag of the forwarded events
+    pub fn map_tag(mut self, f: impl Fn(T) -> T + 'static) -> Self {
+        self.map_tag = Some(Rc::new(f));
+        self
+    }
+}
+
+#[derive(Debug)]
+struct RouterState<T: Tag, I: Id> {
+    routes: Vec<Route<T, I>>,
+    max_hops: Option<usize>,
+    forwarded: usize,
+    loops_prevented: usize,
+}
+
+impl<T: Tag, I: Id> RouterState<T, I> {
+    fn forward(&mut self, from: usize, event: &Event<T, I>) {
+        let trail = match event.get_trail() {
+            [] => vec![from],
+            trail => trail.to_vec(),
+        };
+
+        for route in self.routes.iter().filter(|r| r.from.get_handler_id() == from && r.matcher.matches(event)) {
+            let to = route.to.get_handler_id();
+            if trail.contains(&to) || self.max_hops.is_some_and(|max| trail.len() > max) {
+                #[cfg(test)]
+                println!("Router stopped {:?} looping from EventHandler_{} to EventHandler_{}", event, from, to);
+
+                self.loops_prevented += 1;
+                continue;
+            }
+
+            let tag = match &route.map_tag {
+                Some(f) => event.get_tag().map(|t| f(t)),
+                None => event.get_tag(),
+            };
+            let trail: Vec<usize> = trail.iter().copied().chain([to]).collect();
+            self.forwarded += 1;
+            route.to.push(event.forward(tag, &trail));
+        }
+    }
+}
+
+/// Forwards consumed events between several `EventHandler`s according to `Route`s.
+///
+/// The router only keeps weak references to its handlers. Events are never
+/// forwarded into a handler they already went through, as recorded in their
+/// `Event::get_trail`, and forwarding stops once the router is dropped.
+/// Forwarded events keep the emitter of the consumed event and are caused by it.
+#[derive(Debug)]
+pub struct Router<T: Tag, I: Id> {
+    state: Rc<RefCell<RouterState<T, I>>>,
+    /// Observers of the handlers routes start from, by handler id
+    attached: Vec<(usize, ObserverHandle<T, I>)>,
+}
+
+impl<T: Tag, I: Id> Default for Router<T, I> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Tag, I: Id> Router<T, I> {
+    pub fn new() -> Self {
+        Router {
+            state: Rc::new(RefCell::new(RouterState {
+                routes: Vec::new(),
+                max_hops: None,
+                forwarded: 0,
+                loops_prevented: 0,
+            })),
+            attached: Vec::new(),
+        }
+    }
+    fn attach(&mut self, eh: &EHRc<T, I>) {
+        let id = eh.borrow().get_id();
+        if self.attached.iter().any(|(attached, _)| *attached == id) {
+            return;
+        }
+        let state: Weak<RefCell<RouterState<T, I>>> = Rc::downgrade(&self.state);
+        let observer = EventHandler::observe(eh, move |e| {
+            if let Some(state) = state.upgrade() {
+                state.borrow_mut().forward(id, e);
+            }
+        });
+        self.attached.push((id, observer));
+    }
+    pub fn add_route(&mut self, route: Route<T, I>) -> Result<(), String> {
+        let Some(from) = route.from.get_handler() else {
+            return Err(format!("{:?} starts from a dropped handler", route));
+        };
+        self.attach(&from);
+        self.state.borrow_mut().routes.push(route);
+        Ok(())
+    }
+    pub fn get_routes(&self) -> Vec<Route<T, I>> {
+        self.state.borrow().routes.clone()
+    }
+    pub fn clear_routes(&mut self) {
+        self.state.borrow_mut().routes.clear();
+    }
+    /// Caps how many times an event can be forwarded, so the handlers in its trail
+    /// after the one it was first consumed by
+    pub fn set_max_hops(&mut self, max_hops: Option<usize>) {
+        self.state.borrow_mut().max_hops = max_hops;
+    }
+    pub fn get_forwarded_count(&self) -> usize {
+        self.state.borrow().forwarded
+    }
+    pub fn get_loops_prevented(&self) -> usize {
+        self.state.borrow().loops_prevented
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        event_handler::EventHandler as EH,
+        def_emitter::DefEmitter as DEm,
+        tests::{TestTags::{self, *}, Recorder},
+    };
+    use super::*;
+
+    fn consume_all(handlers: &[&EHRc<TestTags, usize>]) {
+        while let Some(eh) = handlers.iter().find(|eh| eh.borrow().get_stack_len() > 0) {
+            eh.borrow_mut().consume_next_event();
+        }
+    }
+
+    /// Handlers recording the events they consume
+    fn recorded_handlers(count: usize) -> Vec<(EHRc<TestTags, usize>, Recorder)> {
+        (0..count).map(|_| {
+            let eh = EH::<TestTags, usize>::new_ehrc();
+            let recorder = Recorder::new(vec![T1, T2, T3, T4(1), T4(2)], None);
+            eh.borrow_mut().add_listener(recorder.as_lirc()).unwrap();
+            (eh, recorder)
+        }).collect()
+    }
+
+    #[test]
+    fn route_matching() {
+        let handlers = recorded_handlers(3);
+        let [(ui, _), (net, on_net), (game, on_game)] = &handlers[..] else { unreachable!() };
+        let em = DEm::<TestTags>::new_emrc(None);
+        let other = DEm::<TestTags>::new_emrc(None);
+
+        let mut router = Router::new();
+        router.add_route(Route::new(ui, net, RouteMatch::Tag(T1))).unwrap();
+        router.add_route(Route::new(ui, game, RouteMatch::Emitter(other.borrow().get_id()))).unwrap();
+        router.add_route(Route::new(ui, net, RouteMatch::Predicate(Rc::new(|e| matches!(e.get_tag(), Some(T4(_)))))).map_tag(|t| match t {
+            T4(n) => T4(n + 1),
+            t => t,
+        })).unwrap();
+
+        ui.borrow_mut().emit(em.clone(), T1);
+        ui.borrow_mut().emit(other.clone(), T3);
+        ui.borrow_mut().emit(em.clone(), T4(1));
+        ui.borrow_mut().emit(em.clone(), T2);
+        consume_all(&[ui, net, game]);
+
+        assert_eq!(on_net.received_tags(), vec![Some(T1), Some(T4(2))]);
+        assert_eq!(on_game.received_tags(), vec![Some(T3)]);
+        assert_eq!(router.get_forwarded_count(), 3);
+        assert_eq!(router.get_loops_prevented(), 0);
+    }
+
+    #[test]
+    fn forwarded_events_keep_their_origin() {
+        let handlers = recorded_handlers(2);
+        let [(ui, _), (net, on_net)] = &handlers[..] else { unreachable!() };
+        let em = DEm::<TestTags>::new_emrc(None);
+        let mut router = Router::new();
+        router.add_route(Route::new(ui, net, RouteMatch::Any)).unwrap();
+
+        let sent = Event::new(em.clone(), Some(T1)).with_header("user", "ob");
+        ui.borrow_mut().push_event(Some(sent.clone()));
+        let consumed = sent.get_id();
+        consume_all(&[ui, net]);
+
+        let forwarded = &on_net.received.borrow()[0];
+        assert_ne!(forwarded.get_id(), consumed);
+        assert_eq!(forwarded.get_cause(), Some(consumed));
+        assert_eq!(forwarded.get_emitter().borrow().get_id(), em.borrow().get_id());
+        assert_eq!(forwarded.get_trail(), [ui.borrow().get_id(), net.borrow().get_id()]);
+        assert_eq!((forwarded.get_timestamp(), forwarded.get_header("user")), (sent.get_timestamp(), Some("ob")));
+        assert_eq!(forwarded.get_handler_id(), Some(net.borrow().get_id()));
+        assert!(forwarded.get_sequence() > sent.get_sequence());
+    }
+
+    #[test]
+    fn loops_stop_at_visited_handlers() {
+        let handlers = recorded_handlers(3);
+        let [(a, on_a), (b, on_b), (c, on_c)] = &handlers[..] else { unreachable!() };
+        let em = DEm::<TestTags>::new_emrc(None);
+        let mut router = Router::new();
+        for (from, to) in [(a, b), (b, c), (c, a), (b, a)] {
+            router.add_route(Route::new(from, to, RouteMatch::Any)).unwrap();
+        }
+
+        a.borrow_mut().emit(em.clone(), T1);
+        consume_all(&[a, b, c]);
+
+        // a -> b -> c, while b -> a and c -> a go back to where the event started
+        assert_eq!(on_a.received_tags(), vec![Some(T1)]);
+        assert_eq!(on_b.received_tags(), vec![Some(T1)]);
+        assert_eq!(on_c.received_tags(), vec![Some(T1)]);
+        let ids = [a, b, c].map(|eh| eh.borrow().get_id());
+        assert_eq!(on_b.received.borrow()[0].get_trail(), &ids[..2]);
+        assert_eq!(on_c.received.borrow()[0].get_trail(), ids);
+        assert_eq!(router.get_forwarded_count(), 2);
+        assert_eq!(router.get_loops_prevented(), 2);
+    }
+
+    #[test]
+    fn max_hops() {
+        let handlers = recorded_handlers(4);
+        let em = DEm::<TestTags>::new_emrc(None);
+        let mut router = Router::new();
+        for pair in handlers.windows(2) {
+            router.add_route(Route::new(&pair[0].0, &pair[1].0, RouteMatch::Any)).unwrap();
+        }
+        router.set_max_hops(Some(2));
+
+        handlers[0].0.borrow_mut().emit(em.clone(), T1);
+        consume_all(&handlers.iter().map(|(eh, _)| eh).collect::<Vec<_>>());
+
+        let received: Vec<usize> = handlers.iter().map(|(_, r)| r.received.borrow().len()).collect();
+        assert_eq!(received, vec![1, 1, 1, 0]);
+        assert_eq!(router.get_forwarded_count(), 2);
+        assert_eq!(router.get_loops_prevented(), 1);
+    }
+
+    #[test]
+    fn dropping_the_router_stops_forwarding() {
+        let handlers = recorded_handlers(2);
+        let [(ui, _), (net, _)] = &handlers[..] else { unreachable!() };
+        let em = DEm::<TestTags>::new_emrc(None);
+        let mut router = Router::new();
+        router.add_route(Route::new(ui, net, RouteMatch::Any)).unwrap();
+        router.add_route(Route::new(ui, net, RouteMatch::Tag(T1))).unwrap();
+        assert_eq!(ui.borrow().get_observer_count(), 1);
+
+        drop(router);
+        assert_eq!(ui.borrow().get_observer_count(), 0);
+        ui.borrow_mut().emit(em.clone(), T1);
+        ui.borrow_mut().consume_next_event();
+        assert_eq!(net.borrow().get_stack_len(), 0);
+    }
+}
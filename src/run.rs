@@ -0,0 +1,154 @@
+use std::time::{Duration, Instant};
+use crate::{prelude::*, event_handler::EventHandler};
+
+/// Bounds on `EventHandler::run_until_empty`, `None` meaning unbounded
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct RunLimits {
+    pub max_events: Option<usize>,
+    /// Events emitted deeper than this into a chain of reactions are not consumed
+    pub max_depth: Option<usize>,
+    pub max_time: Option<Duration>,
+    /// Stop as soon as a tag reappears in its own chain of reactions
+    pub stop_on_cycle: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StopReason {
+    Empty,
+    MaxEvents,
+    MaxDepth,
+    MaxTime,
+    Cycle,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunReport<T: Tag> {
+    pub consumed: usize,
+    pub stopped: StopReason,
+    /// First chain of tags found to lead back to its own first tag, e.g. `[A, B, A]`
+    pub cycle: Option<Vec<T>>,
+    pub elapsed: Duration,
+}
+
+impl<T: Tag, I: Id> EventHandler<T, I> {
+    /// Consumes events, including the ones emitted while dispatching,
+    /// until the stack is empty or one of `limits` is hit.
+    /// Events left over when stopping early stay on the stack.
+    pub fn run_until_empty(&mut self, limits: RunLimits) -> RunReport<T> {
+        let start = Instant::now();
+        let mut consumed = 0;
+        let mut cycle = None;
+        // Tags of the event being consumed and of the events that led to it.
+        // Reactions are pushed on top of the stack, so they are consumed depth first.
+        let mut chain: Vec<Option<T>> = vec![];
+
+        let stopped = loop {
+            self.drain_inbox();
+            let Some(next) = self.peek_next() else { break StopReason::Empty };
+            let (depth, tag) = (next.get_depth(), next.get_tag());
+
+            if limits.max_events.is_some_and(|max| consumed >= max) {
+                break StopReason::MaxEvents;
+            }
+            if limits.max_time.is_some_and(|max| start.elapsed() >= max) {
+                break StopReason::MaxTime;
+            }
+
+            chain.truncate(depth);
+            if cycle.is_none() && tag.is_some() && let Some(pos) = chain.iter().position(|t| *t == tag) {
+                cycle = Some(chain[pos..].iter().chain([&tag]).filter_map(|t| *t).collect());
+                if limits.stop_on_cycle {
+                    break StopReason::Cycle;
+                }
+            }
+            if limits.max_depth.is_some_and(|max| depth > max) {
+                break StopReason::MaxDepth;
+            }
+
+            chain.push(tag);
+            self.consume_next_event();
+            consumed += 1;
+        };
+
+        #[cfg(test)]
+        println!("{} ran {} events, stopped: {:?}", self, consumed, stopped);
+
+        RunReport { consumed, stopped, cycle, elapsed: start.elapsed() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        event_handler::EventHandler as EH,
+        def_emitter::DefEmitter as DEm,
+        state_machine::{StateMachine, InvalidPolicy},
+        tests::TestTags::{self, *},
+    };
+    use super::*;
+
+    /// Handler whose state machine reacts to `a` with `b` and to `b` with `c`
+    fn chained(a: TestTags, b: TestTags, c: TestTags) -> EHRc<TestTags, usize> {
+        let eh = EH::<TestTags, usize>::new_ehrc();
+        let mut sm = StateMachine::new((), InvalidPolicy::Ignore);
+        sm.add_transition((), a, (), Some(b));
+        sm.add_transition((), b, (), Some(c));
+        sm.register(&eh).unwrap();
+        eh
+    }
+
+    #[test]
+    fn runs_reactions_until_empty() {
+        let eh = chained(T1, T2, T3);
+        let em = DEm::<TestTags>::new_emrc(None);
+
+        eh.borrow_mut().emit(em.clone(), T1);
+        eh.borrow_mut().emit(em.clone(), T2);
+        let report = eh.borrow_mut().run_until_empty(RunLimits::default());
+        assert_eq!((report.consumed, report.stopped, report.cycle), (5, StopReason::Empty, None));
+    }
+
+    #[test]
+    fn max_depth_stops_a_ping_pong() {
+        let eh = chained(T1, T2, T1);
+        let em = DEm::<TestTags>::new_emrc(None);
+
+        eh.borrow_mut().emit(em.clone(), T1);
+        let report = eh.borrow_mut().run_until_empty(RunLimits { max_depth: Some(10), ..Default::default() });
+        assert_eq!((report.consumed, report.stopped), (11, StopReason::MaxDepth));
+        assert_eq!(report.cycle, Some(vec![T1, T2, T1]));
+        assert_eq!(eh.borrow().peek_next().unwrap().get_depth(), 11);
+    }
+
+    #[test]
+    fn max_events() {
+        let eh = chained(T1, T2, T1);
+        let em = DEm::<TestTags>::new_emrc(None);
+
+        eh.borrow_mut().emit(em.clone(), T1);
+        let report = eh.borrow_mut().run_until_empty(RunLimits { max_events: Some(3), ..Default::default() });
+        assert_eq!((report.consumed, report.stopped), (3, StopReason::MaxEvents));
+        assert_eq!(eh.borrow().get_stack_len(), 1);
+    }
+
+    #[test]
+    fn stop_on_cycle() {
+        let eh = chained(T1, T2, T1);
+        let em = DEm::<TestTags>::new_emrc(None);
+
+        eh.borrow_mut().emit(em.clone(), T2);
+        let report = eh.borrow_mut().run_until_empty(RunLimits { stop_on_cycle: true, ..Default::default() });
+        assert_eq!((report.consumed, report.stopped), (2, StopReason::Cycle));
+        assert_eq!(report.cycle, Some(vec![T2, T1, T2]));
+    }
+
+    #[test]
+    fn max_time() {
+        let eh = chained(T1, T2, T1);
+        let em = DEm::<TestTags>::new_emrc(None);
+
+        eh.borrow_mut().emit(em.clone(), T1);
+        let report = eh.borrow_mut().run_until_empty(RunLimits { max_time: Some(Duration::ZERO), ..Default::default() });
+        assert_eq!((report.consumed, report.stopped), (0, StopReason::MaxTime));
+    }
+}
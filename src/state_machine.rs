@@ -0,0 +1,269 @@
+use std::rc::Weak;
+use crate::{prelude::*, event::Event, inbox::Inbox, IDCOUNTER};
+
+pub trait State = Debug + PartialEq + Clone + 'static;
+
+/// What a `StateMachine` does with a trigger that has
+/// no transition out of its current state
+#[derive(Debug, Clone, PartialEq)]
+pub enum InvalidPolicy<T: Tag> {
+    Ignore,
+    /// Record the rejected trigger, see `StateMachine::take_errors`
+    Error,
+    /// Emit the given tag from the state machine into its handlers
+    Emit(T),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvalidTransition<S: State, T: Tag> {
+    pub state: S,
+    pub trigger: T,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Transition<S: State, T: Tag> {
+    from: S,
+    trigger: T,
+    to: S,
+    emit: Option<T>,
+}
+
+type Action<S> = Rc<dyn Fn(&S)>;
+
+pub type SmRC<S, T> = Rc<RefCell<StateMachine<S, T>>>;
+
+/// Finite state machine driven by the events of an `EventHandler`.
+/// Transitions are keyed by (state, tag) and may emit a tag of their own
+/// back into the handler the machine is registered with.
+#[derive(Clone)]
+pub struct StateMachine<S: State, T: Tag> {
+    id: usize,
+    state: RefCell<S>,
+    transitions: Vec<Transition<S, T>>,
+    entry_actions: Vec<(S, Action<S>)>,
+    exit_actions: Vec<(S, Action<S>)>,
+    policy: InvalidPolicy<T>,
+    errors: RefCell<Vec<InvalidTransition<S, T>>>,
+    outputs: Vec<Inbox<T, usize>>,
+    this: Weak<RefCell<Self>>,
+}
+
+impl<S: State, T: Tag> Debug for StateMachine<S, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StateMachine")
+            .field("id", &self.id)
+            .field("state", &self.state.borrow())
+            .field("transitions", &self.transitions)
+            .field("policy", &self.policy)
+            .field("outputs", &self.outputs)
+            .finish()
+    }
+}
+
+impl<S: State, T: Tag> EmitObj<usize> for StateMachine<S, T> {
+    fn get_id(&self) -> usize {
+        self.id
+    }
+}
+
+impl<S: State, T: Tag> StateMachine<S, T> {
+    pub fn new(initial: S, policy: InvalidPolicy<T>) -> Self {
+        Self {
+            id: IDCOUNTER.fetch_add(1, std::sync::atomic::Ordering::SeqCst),
+            state: RefCell::new(initial),
+            transitions: Vec::new(),
+            entry_actions: Vec::new(),
+            exit_actions: Vec::new(),
+            policy,
+            errors: RefCell::new(Vec::new()),
+            outputs: Vec::new(),
+            this: Weak::new(),
+        }
+    }
+    pub fn into_smrc(self) -> SmRC<S, T> {
+        Rc::new_cyclic(|this| {
+            let mut sm = self;
+            sm.this = this.clone();
+            RefCell::new(sm)
+        })
+    }
+    /// Adds the machine as a listener of `eh` and
+    /// sends its transition events back into it
+    pub fn register(mut self, eh: &EHRc<T, usize>) -> Result<SmRC<S, T>, String> {
+        self.outputs.push(Inbox::new(eh));
+        let sm = self.into_smrc();
+        eh.borrow_mut().add_listener(LiRC(sm.clone()))?;
+        Ok(sm)
+    }
+    /// Replaces any existing transition from `from` on `trigger`
+    pub fn add_transition(&mut self, from: S, trigger: T, to: S, emit: Option<T>) {
+        self.transitions.retain(|t| !(t.from == from && t.trigger == trigger));
+        self.transitions.push(Transition { from, trigger, to, emit });
+        crate::listener::triggers_changed();
+    }
+    pub fn on_entry(&mut self, state: S, action: impl Fn(&S) + 'static) {
+        self.entry_actions.push((state, Rc::new(action)));
+    }
+    pub fn on_exit(&mut self, state: S, action: impl Fn(&S) + 'static) {
+        self.exit_actions.push((state, Rc::new(action)));
+    }
+    pub fn set_policy(&mut self, policy: InvalidPolicy<T>) {
+        self.policy = policy;
+    }
+    pub fn get_state(&self) -> S {
+        self.state.borrow().clone()
+    }
+    pub fn is_in(&self, state: &S) -> bool {
+        *self.state.borrow() == *state
+    }
+    pub fn can_fire(&self, trigger: &T) -> bool {
+        self.find_transition(trigger).is_some()
+    }
+    pub fn take_errors(&self) -> Vec<InvalidTransition<S, T>> {
+        std::mem::take(&mut *self.errors.borrow_mut())
+    }
+    fn find_transition(&self, trigger: &T) -> Option<&Transition<S, T>> {
+        let state = self.state.borrow();
+        self.transitions.iter().find(|t| t.from == *state && t.trigger == *trigger)
+    }
+    fn emit_out(&self, tag: T) {
+        for out in &self.outputs {
+            out.emit(self.as_emrc(), tag);
+        }
+    }
+    /// Runs the transition for `trigger` from the current state, applying
+    /// the invalid transition policy if there is none. Returns the new state.
+    pub fn fire(&self, trigger: T) -> Result<S, InvalidTransition<S, T>> {
+        let Some(Transition { to, emit, .. }) = self.find_transition(&trigger).cloned() else {
+            let invalid = InvalidTransition { state: self.get_state(), trigger };
+            match self.policy {
+                InvalidPolicy::Ignore => {}
+                InvalidPolicy::Error => self.errors.borrow_mut().push(invalid.clone()),
+                InvalidPolicy::Emit(tag) => self.emit_out(tag),
+            }
+            return Err(invalid);
+        };
+
+        let from = self.get_state();
+        for (_, action) in self.exit_actions.iter().filter(|(s, _)| *s == from) {
+            action(&from);
+        }
+        *self.state.borrow_mut() = to.clone();
+        for (_, action) in self.entry_actions.iter().filter(|(s, _)| *s == to) {
+            action(&to);
+        }
+        if let Some(tag) = emit {
+            self.emit_out(tag);
+        }
+        Ok(to)
+    }
+}
+
+impl<S: State, T: Tag> IListener<T, usize> for StateMachine<S, T> {
+    fn get_triggers(&self) -> Vec<&T> {
+        let mut ret: Vec<&T> = vec![];
+        for t in &self.transitions {
+            if !ret.contains(&&t.trigger) {
+                ret.push(&t.trigger);
+            }
+        }
+        ret
+    }
+    fn has_trigger(&self, tag: &T) -> bool {
+        self.transitions.iter().any(|t| t.trigger == *tag)
+    }
+    fn on_triggers(&self, triggers: Vec<Event<T, usize>>) {
+        for tag in triggers.iter().filter_map(|e| e.get_tag()) {
+            let _ = self.fire(tag);
+        }
+    }
+    fn as_lirc(&self) -> LiRC<T, usize> {
+        match self.this.upgrade() {
+            Some(sm) => LiRC(sm),
+            None => LiRC(self.clone().into_smrc()),
+        }
+    }
+    fn into_lirc(self) -> Result<LiRC<T, usize>, &'static str> {
+        Ok(LiRC(self.into_smrc()))
+    }
+    fn try_into_lirc(self) -> Option<LiRC<T, usize>> {
+        Some(LiRC(self.into_smrc()))
+    }
+    fn as_emrc(&self) -> EmRC<usize> {
+        match self.this.upgrade() {
+            Some(sm) => EmRC(sm),
+            None => EmRC(self.clone().into_smrc()),
+        }
+    }
+    fn into_emrc(self) -> EmRC<usize> {
+        EmRC(self.into_smrc())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use crate::{event_handler::EventHandler as EH, def_emitter::DefEmitter as DEm, tests::TestTags::{self, *}};
+    use super::*;
+
+    #[derive(Debug, PartialEq, Clone)]
+    enum Door { Closed, Open }
+
+    fn door(policy: InvalidPolicy<TestTags>) -> StateMachine<Door, TestTags> {
+        let mut sm = StateMachine::new(Door::Closed, policy);
+        sm.add_transition(Door::Closed, T1, Door::Open, Some(T3));
+        sm.add_transition(Door::Open, T2, Door::Closed, None);
+        sm
+    }
+
+    #[test]
+    fn transitions_run_actions_and_emit() {
+        let eh = EH::<TestTags, usize>::new_ehrc();
+        let em = DEm::<TestTags>::new_emrc(None);
+        let entries = Rc::new(Cell::new(0));
+        let exits = Rc::new(Cell::new(0));
+
+        let mut sm = door(InvalidPolicy::Ignore);
+        let counter = entries.clone();
+        sm.on_entry(Door::Open, move |_| counter.set(counter.get() + 1));
+        let counter = exits.clone();
+        sm.on_exit(Door::Closed, move |_| counter.set(counter.get() + 1));
+        let sm = sm.register(&eh).unwrap();
+
+        eh.borrow_mut().emit(em.clone(), T1);
+        eh.borrow_mut().consume_next_event();
+        assert!(sm.borrow().is_in(&Door::Open));
+        assert_eq!((entries.get(), exits.get()), (1, 1));
+        assert_eq!(eh.borrow().peek_next_tag(), Some(T3));
+        assert_eq!(eh.borrow().peek_next_emitter().unwrap(), sm.borrow().as_emrc());
+
+        eh.borrow_mut().pop_next();
+        eh.borrow_mut().emit(em.clone(), T2);
+        eh.borrow_mut().consume_next_event();
+        assert!(sm.borrow().is_in(&Door::Closed));
+        assert_eq!(eh.borrow().get_stack_len(), 0);
+    }
+
+    #[test]
+    fn invalid_trigger_emits_policy_tag() {
+        let eh = EH::<TestTags, usize>::new_ehrc();
+        let em = DEm::<TestTags>::new_emrc(None);
+        let sm = door(InvalidPolicy::Emit(T4(-1))).register(&eh).unwrap();
+
+        eh.borrow_mut().emit(em.clone(), T2);
+        eh.borrow_mut().consume_next_event();
+        assert!(sm.borrow().is_in(&Door::Closed));
+        assert_eq!(eh.borrow().get_stack_tags(), vec![Some(T4(-1))]);
+    }
+
+    #[test]
+    fn invalid_trigger_is_recorded() {
+        let sm = door(InvalidPolicy::Error);
+
+        assert!(!sm.can_fire(&T2));
+        assert_eq!(sm.fire(T2), Err(InvalidTransition { state: Door::Closed, trigger: T2 }));
+        assert_eq!(sm.take_errors(), vec![InvalidTransition { state: Door::Closed, trigger: T2 }]);
+        assert!(sm.take_errors().is_empty());
+        assert_eq!(sm.fire(T1), Ok(Door::Open));
+    }
+}
@@ -0,0 +1,175 @@
+use crate::{prelude::*, event::Event, event_handler::EventHandler, actor};
+
+/// Last consumed event of each sticky tag
+#[derive(Clone, Debug)]
+pub(crate) struct Retained<T: Tag, I: Id> {
+    sticky: Vec<T>,
+    /// In the order they were consumed
+    events: Vec<Event<T, I>>,
+}
+
+impl<T: Tag, I: Id> Default for Retained<T, I> {
+    fn default() -> Self {
+        Self { sticky: Vec::new(), events: Vec::new() }
+    }
+}
+
+impl<T: Tag, I: Id> Retained<T, I> {
+    fn is_sticky(&self, tag: &T) -> bool {
+        self.sticky.contains(tag)
+    }
+    /// Keeps `event` in place of the event it retains, if its tag is sticky
+    pub(crate) fn record(&mut self, event: &Event<T, I>) {
+        let Some(tag) = event.get_tag() else { return };
+        if event.get_correlation_id().is_some() || !self.is_sticky(&tag) {
+            return;
+        }
+        self.forget(&tag);
+        self.events.push(event.clone());
+    }
+    fn forget(&mut self, tag: &T) -> Option<Event<T, I>> {
+        let pos = self.events.iter().position(|e| e.get_tag().as_ref() == Some(tag))?;
+        Some(self.events.remove(pos))
+    }
+}
+
+impl<T: Tag, I: Id> EventHandler<T, I> {
+    /// From now on, the handler retains the last consumed event tagged `tag`,
+    /// and hands it to every listener added later that it triggers.
+    /// Tags are compared with `==`, so `T4(1)` and `T4(2)` are retained separately.
+    pub fn set_sticky(&mut self, tag: T) {
+        let retained = self.get_retained_mut();
+        if !retained.is_sticky(&tag) {
+            retained.sticky.push(tag);
+        }
+    }
+    /// Stops retaining events tagged `tag`, returns the event that was retained
+    pub fn unset_sticky(&mut self, tag: &T) -> Option<Event<T, I>> {
+        let retained = self.get_retained_mut();
+        retained.sticky.retain(|t| t != tag);
+        retained.forget(tag)
+    }
+    pub fn is_sticky(&self, tag: &T) -> bool {
+        self.get_retained().is_sticky(tag)
+    }
+    /// Retained events, in the order they were consumed
+    pub fn get_retained_events(&self) -> &Vec<Event<T, I>> {
+        &self.get_retained().events
+    }
+    /// Forgets the event retained for `tag`, which stays sticky
+    pub fn clear_retained(&mut self, tag: &T) -> Option<Event<T, I>> {
+        self.get_retained_mut().forget(tag)
+    }
+    /// Hands `listener` the retained events it is triggered by, as they were when consumed.
+    /// In actor mode they go to its mailbox.
+    pub(crate) fn replay_retained(&mut self, listener: &LiRC<T, I>) {
+        let events: Vec<Event<T, I>> = self.get_retained_events().iter()
+            .filter(|e| e.get_tag().is_some_and(|t| listener.borrow().has_trigger(&t)))
+            .cloned()
+            .collect();
+        for e in events {
+            #[cfg(test)]
+            println!("{} replayed retained {:?} to {:?}", self, e, listener.borrow().get_id());
+
+            // Events the listener emitted are drained while still caused by `e`
+            let dead = self.with_dispatching(&e, |eh| {
+                let dead = actor::deliver(eh.get_actors_mut(), [listener], &e);
+                eh.drain_inbox();
+                dead
+            });
+            if let Some(letter) = dead {
+                self.bury(letter);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        def_emitter::DefEmitter as DEm,
+        actor::Fairness,
+        state_machine::{StateMachine, InvalidPolicy},
+        tests::{TestTags::{self, *}, Recorder},
+    };
+    use super::*;
+
+    type EH = EventHandler<TestTags, usize>;
+
+    /// Handler with T4(1), T4(2) and T1 sticky, having consumed T4(2), T4(1), T1, T2, T4(7) and T4(2)
+    fn sticky_handler() -> EH {
+        let mut eh = EH::new();
+        let em = DEm::<TestTags>::new_emrc(None);
+        eh.set_sticky(T4(1));
+        eh.set_sticky(T4(2));
+        eh.set_sticky(T1);
+        for tag in [T4(2), T4(1), T1, T2, T4(7), T4(2)] {
+            eh.emit(em.clone(), tag);
+            eh.consume_next_event();
+        }
+        eh
+    }
+
+    #[test]
+    fn last_event_of_each_tag_is_retained() {
+        let eh = sticky_handler();
+
+        assert!(eh.is_sticky(&T4(2)) && !eh.is_sticky(&T4(7)) && !eh.is_sticky(&T2));
+        // Tags of the same variant are retained separately
+        let retained: Vec<_> = eh.get_retained_events().iter().map(|e| e.get_tag()).collect();
+        assert_eq!(retained, vec![Some(T4(1)), Some(T1), Some(T4(2))]);
+    }
+
+    #[test]
+    fn late_listeners_get_the_retained_events_they_are_triggered_by() {
+        let mut eh = sticky_handler();
+
+        let panel = Recorder::new(vec![T1, T2, T4(2)], None);
+        eh.add_listener(panel.as_lirc()).unwrap();
+        assert_eq!(panel.received_tags(), vec![Some(T1), Some(T4(2))]);
+        let other = Recorder::new(vec![T3], None);
+        eh.add_listener(other.as_lirc()).unwrap();
+        assert_eq!(other.received_tags(), vec![]);
+    }
+
+    #[test]
+    fn replay_emissions_are_caused_by_the_retained_event() {
+        let eh = sticky_handler();
+        let mut sm = StateMachine::new((), InvalidPolicy::Ignore);
+        sm.add_transition((), T4(1), (), Some(T3));
+        let eh = eh.into_ehrc();
+        sm.register(&eh).unwrap();
+
+        let replayed = eh.borrow().get_retained_events()[0].clone();
+        let eh = eh.borrow();
+        let emitted = eh.peek_next().unwrap();
+        assert_eq!(emitted.get_tag(), Some(T3));
+        assert_eq!((emitted.get_cause(), emitted.get_depth()), (Some(replayed.get_id()), 1));
+    }
+
+    #[test]
+    fn cleared_and_unset_tags() {
+        let mut eh = sticky_handler();
+
+        assert_eq!(eh.clear_retained(&T1).and_then(|e| e.get_tag()), Some(T1));
+        assert!(eh.is_sticky(&T1));
+        assert_eq!(eh.unset_sticky(&T4(2)).and_then(|e| e.get_tag()), Some(T4(2)));
+        assert!(!eh.is_sticky(&T4(2)));
+        let late = Recorder::new(vec![T1, T4(2)], None);
+        eh.add_listener(late.as_lirc()).unwrap();
+        assert_eq!(late.received_tags(), vec![]);
+    }
+
+    #[test]
+    fn retained_events_wait_in_mailboxes() {
+        let mut eh = sticky_handler();
+
+        eh.set_actor_mode(Some(Fairness::RoundRobin));
+        let actor = Recorder::new(vec![T1], None);
+        eh.add_listener(actor.as_lirc()).unwrap();
+        assert_eq!(actor.received_tags(), vec![]);
+        assert_eq!(eh.get_mailbox_len(&actor.as_lirc()), 1);
+        eh.run_actors_until_idle();
+        assert_eq!(actor.received_tags(), vec![Some(T1)]);
+    }
+}
@@ -1,5 +1,6 @@
 use crate::{prelude::*, event::Event};
 use crate::{IDCOUNTER, event_handler::EventHandler};
+use crate::dead_letter::{self, DeadLetter, DeadLetterReason, DeadLetterSink, DeadLetters};
 
 // Event handler reporting to a parent object
 #[derive(Clone)]
@@ -9,17 +10,19 @@ pub struct SubEventHandler<'a, P: EHParent<T, I> + Debug, T: Tag, I: Id> {
     prev_event: Option<Event<T, I>>,
     listeners: Vec<LiRC<T, I>>,
     parents: Vec<&'a P>,
+    dead_letters: DeadLetters<T, I>,
 }
 
 impl<'a, P: EHParent<T, I> + Debug, T: Tag, I: Id> Debug for SubEventHandler<'a, P, T, I> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let prev_event_str = if self.prev_event.is_some() { &Some((self.prev_event.as_ref().unwrap().get_emitter().borrow().get_id(), self.prev_event.as_ref().unwrap().get_tag())) } else { &None as &Option<(I, Option<T>)> };
+        let prev_event_str = &self.prev_event.as_ref().map(|e| (e.get_emitter().borrow().get_id(), e.get_tag()));
 
         f.debug_struct("SubEventHandler")
             .field("id", &self.id)
             .field("stack", &self.stack.iter().map(|e| (e.get_emitter().borrow().get_id(), e.get_tag())).collect::<Vec<(I, Option<T>)>>())
             .field("prev_event", prev_event_str)
             .field("listeners", &self.listeners.iter().map(|l| l.borrow().get_id()).collect::<Vec<I>>())
+            .field("dead letters", &self.dead_letters.get_count())
             .finish()
     }
 }
@@ -43,27 +46,29 @@ impl<'a, P: EHParent<T, I> + Debug, T: Tag, I: Id> SubEventHandler<'a, P, T, I>
             stack: Vec::new(),
             prev_event: None,
             listeners: Vec::new(),
-            parents
+            parents,
+            dead_letters: DeadLetters::default(),
         }
     }
     pub fn get_id(&self) -> usize {
         self.id
     }
     pub fn push_event(&mut self, event: Option<Event<T, I>>) {
-        match event {
-            Some(e) => {
-                #[cfg(debug_assertions)]
-                println!("Event pushed to stack: {:?}", e);
+        if let Some(mut e) = event {
+            e.set_handler_id(self.id);
+
+            #[cfg(debug_assertions)]
+            println!("Event pushed to stack: {:?}", e);
 
-                self.stack.push(e)
-            },
-            _ => {}
+            self.stack.push(e)
         }
     }
     pub fn push_events(&mut self, events: Option<Vec<Event<T, I>>>) {
         match events {
             None => {}
-            Some(e) => {
+            Some(mut e) => {
+                e.iter_mut().for_each(|e| e.set_handler_id(self.id));
+
                 #[cfg(debug_assertions)]
                 println!("Events pushed to stack: {:?}", e);
 
@@ -75,10 +80,57 @@ impl<'a, P: EHParent<T, I> + Debug, T: Tag, I: Id> SubEventHandler<'a, P, T, I>
         &self.stack
     }
     pub fn get_stack_events(&self) -> Vec<T> {
-        self.get_stack().into_iter().map(|e| e.get_tag().expect("Untagged event")).collect()
+        self.get_stack().iter().map(|e| e.get_tag().expect("Untagged event")).collect()
     }
     pub fn get_stack_emitters(&self) -> Vec<EmRC<I>> {
-        self.get_stack().into_iter().map(|e| e.get_emitter()).collect()
+        self.get_stack().iter().map(|e| e.get_emitter()).collect()
+    }
+    /// Pending event `n` places away from being consumed, `peek_nth(0)` is the next one
+    pub fn peek_nth(&self, n: usize) -> Option<&Event<T, I>> {
+        self.stack.iter().rev().nth(n)
+    }
+    /// Iterates over pending events in the order they will be consumed
+    pub fn iter_stack(&self) -> impl DoubleEndedIterator<Item = &Event<T, I>> {
+        self.stack.iter().rev()
+    }
+    /// Puts `event` at the front of the stack, to be consumed after every pending event
+    pub fn push_event_front(&mut self, mut event: Event<T, I>) {
+        event.set_handler_id(self.id);
+
+        #[cfg(debug_assertions)]
+        println!("Event pushed to front of stack: {:?}", event);
+
+        self.stack.insert(0, event);
+    }
+    /// Keeps only the pending events matching `keep`, returns how many were removed
+    pub fn retain_events(&mut self, keep: impl FnMut(&Event<T, I>) -> bool) -> usize {
+        let len = self.stack.len();
+        self.stack.retain(keep);
+        len - self.stack.len()
+    }
+    /// Removes the pending events matching `remove`, returns how many were removed
+    pub fn remove_events_where(&mut self, mut remove: impl FnMut(&Event<T, I>) -> bool) -> usize {
+        self.retain_events(|e| !remove(e))
+    }
+    pub fn remove_events_by_tag(&mut self, tag: &T) -> usize {
+        self.remove_events_where(|e| e.get_tag().as_ref() == Some(tag))
+    }
+    /// Cancels every pending event from `emitter`, returns how many were removed
+    pub fn cancel_events_from(&mut self, emitter: &EmRC<I>) -> usize {
+        #[cfg(debug_assertions)]
+        println!("Events cancelled from: {:?}", emitter);
+
+        self.remove_events_where(|e| e.get_emitter() == *emitter)
+    }
+    /// Takes every pending event off the stack, in the order they would have been consumed
+    pub fn drain_stack(&mut self) -> impl Iterator<Item = Event<T, I>> + '_ {
+        self.stack.drain(..).rev()
+    }
+    /// Returns how many pending events were cleared
+    pub fn clear_stack(&mut self) -> usize {
+        let len = self.stack.len();
+        self.stack.clear();
+        len
     }
     pub fn add_listener(&mut self, listener: LiRC<T, I>) {
         self.listeners.push(listener)
@@ -86,11 +138,12 @@ impl<'a, P: EHParent<T, I> + Debug, T: Tag, I: Id> SubEventHandler<'a, P, T, I>
     pub fn get_listeners(&self) -> &Vec<LiRC<T, I>> {
         &self.listeners
     }
+    /// Event `pop_next` would take, the top of the stack
     pub fn peek_next(&self) -> Option<&Event<T, I>> {
         #[cfg(debug_assertions)]
-        println!("Event peeked: {:?}", self.stack.first());
+        println!("Event peeked: {:?}", self.stack.last());
 
-        self.stack.first()
+        self.stack.last()
     }
     pub fn peek_next_tag(&self) -> Option<T> {
         if let Some(e) = self.peek_next() {
@@ -100,11 +153,7 @@ impl<'a, P: EHParent<T, I> + Debug, T: Tag, I: Id> SubEventHandler<'a, P, T, I>
         }
     }
     pub fn peek_next_emitter(&self) -> Option<EmRC<I>> {
-        if let Some(e) = self.peek_next() {
-            Some(e.get_emitter())
-        } else {
-            None
-        }
+        self.peek_next().map(|e| e.get_emitter())
     }
     pub fn pop_next(&mut self) -> Option<Event<T, I>> {
         let ret = self.stack.pop();
@@ -118,35 +167,100 @@ impl<'a, P: EHParent<T, I> + Debug, T: Tag, I: Id> SubEventHandler<'a, P, T, I>
         &self.prev_event
     }
     pub fn consume_next_event(&mut self) {
-        let next = self.pop_next();
+        if let Some(e) = self.pop_next() {
+            #[cfg(debug_assertions)]
+            println!("Consumed event: {:?}", e);
 
-        match next {
-            Some(e) => {
-                #[cfg(debug_assertions)]
-                println!("Consumed event: {:?}", e);
-
-                self.broadcast_event(e);
-            }
-            None => ()
+            self.broadcast_event(e);
         }
     }
     pub fn broadcast_event(&mut self, event: Event<T, I>) {
         #[cfg(debug_assertions)]
         println!("Broadcast event: {:?}", event);
 
-        for li in self.get_listeners() {
-            if li.borrow().get_triggers().contains(&&event.get_tag().expect("Untagged event")) {
-                li.borrow().on_triggers(vec![event.clone()]);
-            }
-        }
+        let dead = match event.get_tag() {
+            Some(tag) => dead_letter::deliver(self.get_listeners().iter().filter(|li| li.borrow().has_trigger(&tag)), &event),
+            None => Some(DeadLetter { event: event.clone(), reason: DeadLetterReason::Untagged, errors: vec![] }),
+        };
 
         for &p in &self.parents {
             p.notify_parent(&event);
         }
+
+        if let Some(letter) = dead {
+            #[cfg(debug_assertions)]
+            println!("Dead letter: {:?}", letter);
+
+            self.dead_letters.bury(letter);
+        }
+    }
+    pub fn get_dead_letter_sink(&self) -> &DeadLetterSink<T, I> {
+        self.dead_letters.get_sink()
+    }
+    pub fn set_dead_letter_sink(&mut self, sink: DeadLetterSink<T, I>) {
+        self.dead_letters.set_sink(sink);
+    }
+    pub fn get_dead_letters(&self) -> &Vec<DeadLetter<T, I>> {
+        self.dead_letters.get_collected()
+    }
+    pub fn take_dead_letters(&mut self) -> Vec<DeadLetter<T, I>> {
+        self.dead_letters.take_collected()
+    }
+    pub fn get_dead_letter_count(&self) -> usize {
+        self.dead_letters.get_count()
     }
     pub fn broadcast_events(&mut self, events: Vec<Event<T, I>>) {
         for e in events {
             self.broadcast_event(e);
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{def_emitter::DefEmitter as DEm, tests::TestTags::{self, *}};
+    use super::*;
+
+    #[derive(Debug)]
+    struct Parent;
+
+    impl EHParent<TestTags, usize> for Parent {
+        fn notify_parent(&self, _event: &Event<TestTags, usize>) {}
+    }
+
+    #[test]
+    fn stack_editing() {
+        let em1 = DEm::<TestTags>::new_emrc(None);
+        let em2 = DEm::<TestTags>::new_emrc(None);
+        let parent = Parent;
+        let mut seh = SubEventHandler::<Parent, TestTags, usize>::new(vec![&parent]);
+        seh.push_events(Some(vec![Event::new(em1.clone(), Some(T1)), Event::new(em2.clone(), Some(T2)), Event::new(em1.clone(), Some(T3))]));
+
+        assert_eq!(seh.peek_nth(0), seh.peek_next());
+        assert_eq!(seh.peek_nth(0).unwrap().get_tag(), Some(T3));
+        assert_eq!(seh.cancel_events_from(&em1), 2);
+        seh.push_event_front(Event::new(em1.clone(), Some(T1)));
+        assert_eq!(seh.iter_stack().map(|e| e.get_tag()).collect::<Vec<_>>(), vec![Some(T2), Some(T1)]);
+        assert_eq!(seh.peek_next_tag(), seh.pop_next().and_then(|e| e.get_tag()));
+        assert_eq!(seh.clear_stack(), 1);
+    }
+
+    #[test]
+    fn dead_letters() {
+        use crate::{dead_letter::{DeadLetterReason, DeadLetterSink}, tests::Recorder};
+
+        let em = DEm::<TestTags>::new_emrc(None);
+        let mut failing = Recorder::new(vec![T3], None);
+        failing.error = Some("boom");
+        let parent = Parent;
+        let mut seh = SubEventHandler::<Parent, TestTags, usize>::new(vec![&parent]);
+        seh.add_listener(failing.as_lirc());
+        seh.set_dead_letter_sink(DeadLetterSink::Collect);
+        seh.push_events(Some(vec![Event::new(em.clone(), None), Event::new(em.clone(), Some(T3))]));
+        seh.consume_next_event();
+        seh.consume_next_event();
+
+        assert_eq!(seh.get_dead_letters().iter().map(|l| l.reason).collect::<Vec<_>>(), vec![DeadLetterReason::AllFailed, DeadLetterReason::Untagged]);
+        assert_eq!(seh.get_dead_letter_count(), 2);
+    }
+}
@@ -0,0 +1,281 @@
+use std::{collections::HashMap, hash::{Hash, Hasher}, sync::{Mutex, OnceLock}};
+use crate::{prelude::*, event::Event, event_handler::EventHandler, IDCOUNTER};
+
+/// Interned path, split once so matching needs neither the lock nor allocations
+struct Entry {
+    id: u32,
+    path: &'static str,
+    segments: Box<[&'static str]>,
+    is_pattern: bool,
+}
+
+fn interner() -> &'static Mutex<HashMap<&'static str, &'static Entry>> {
+    static INTERNER: OnceLock<Mutex<HashMap<&'static str, &'static Entry>>> = OnceLock::new();
+    INTERNER.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Interned hierarchical topic such as `ui.button.click`, usable as a `Tag`.
+///
+/// Topics double as subscription patterns: a `*` segment matches exactly one
+/// segment and a `#` segment matches any number of segments, including none.
+///
+/// Interning leaks every distinct path for the rest of the program and the interner
+/// never shrinks, so topics should not be built from unbounded input such as user ids.
+#[derive(Clone, Copy)]
+pub struct Topic(&'static Entry);
+
+impl PartialEq for Topic {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.id == other.0.id
+    }
+}
+
+impl Eq for Topic {}
+
+impl Hash for Topic {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.id.hash(state);
+    }
+}
+
+impl Debug for Topic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Topic({})", self.as_str())
+    }
+}
+
+impl Display for Topic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl From<&str> for Topic {
+    fn from(path: &str) -> Self {
+        Topic::new(path)
+    }
+}
+
+impl Topic {
+    pub fn new(path: &str) -> Self {
+        let mut interner = interner().lock().unwrap();
+        if let Some(&entry) = interner.get(path) {
+            return Topic(entry);
+        }
+        let path: &'static str = Box::leak(path.to_owned().into_boxed_str());
+        let segments: Box<[&'static str]> = path.split('.').collect();
+        let is_pattern = segments.iter().any(|&s| s == "*" || s == "#");
+        let entry = Box::leak(Box::new(Entry { id: interner.len() as u32, path, segments, is_pattern }));
+        interner.insert(path, entry);
+        Topic(entry)
+    }
+    /// Topic already interned for `path`, without interning it otherwise
+    pub fn lookup(path: &str) -> Option<Self> {
+        interner().lock().unwrap().get(path).map(|&entry| Topic(entry))
+    }
+    pub fn as_str(&self) -> &'static str {
+        self.0.path
+    }
+    pub fn segments(&self) -> &'static [&'static str] {
+        &self.0.segments
+    }
+    pub fn is_pattern(&self) -> bool {
+        self.0.is_pattern
+    }
+    /// Whether this topic is matched by `pattern`
+    pub fn matches(&self, pattern: Topic) -> bool {
+        *self == pattern || (pattern.is_pattern() && segments_match(self.segments(), pattern.segments()))
+    }
+}
+
+/// Two-pointer match where `#` stands for any run of segments and `*` for exactly one.
+/// On a mismatch only the last `#` seen swallows one more segment, so matching takes
+/// at most `path.len() * pattern.len()` steps however many `#` the pattern has.
+fn segments_match(path: &[&str], pattern: &[&str]) -> bool {
+    let (mut s, mut p) = (0, 0);
+    // Pattern position after the last `#`, and the path position it resumes from
+    let mut last_hash: Option<(usize, usize)> = None;
+    while s < path.len() {
+        match pattern.get(p) {
+            Some(&"#") => {
+                p += 1;
+                last_hash = Some((p, s));
+            }
+            Some(&seg) if seg == "*" || seg == path[s] => {
+                s += 1;
+                p += 1;
+            }
+            _ => match last_hash {
+                Some((after_hash, resume)) => {
+                    last_hash = Some((after_hash, resume + 1));
+                    p = after_hash;
+                    s = resume + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&seg| seg == "#")
+}
+
+impl<I: Id> EventHandler<Topic, I> {
+    /// Emits the interned `path` from `emitter`
+    pub fn publish(&mut self, emitter: EmRC<I>, path: &str) {
+        self.emit(emitter, Topic::new(path));
+    }
+}
+
+type TopicCallback = Rc<dyn Fn(&Event<Topic, usize>)>;
+
+/// Listener subscribed to topic patterns, calling back on every matching event
+#[derive(Clone)]
+pub struct TopicListener {
+    id: usize,
+    patterns: Vec<Topic>,
+    callback: TopicCallback,
+}
+
+impl Debug for TopicListener {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TopicListener")
+            .field("id", &self.id)
+            .field("patterns", &self.patterns)
+            .finish()
+    }
+}
+
+impl EmitObj<usize> for TopicListener {
+    fn get_id(&self) -> usize {
+        self.id
+    }
+}
+
+impl TopicListener {
+    pub fn new(patterns: &[&str], callback: impl Fn(&Event<Topic, usize>) + 'static) -> Self {
+        Self {
+            id: IDCOUNTER.fetch_add(1, std::sync::atomic::Ordering::SeqCst),
+            patterns: patterns.iter().map(|&p| Topic::new(p)).collect(),
+            callback: Rc::new(callback),
+        }
+    }
+    pub fn new_lirc(patterns: &[&str], callback: impl Fn(&Event<Topic, usize>) + 'static) -> LiRC<Topic, usize> {
+        LiRC(Rc::new(RefCell::new(Self::new(patterns, callback))))
+    }
+    pub fn subscribe(&mut self, pattern: &str) {
+        let pattern = Topic::new(pattern);
+        if !self.patterns.contains(&pattern) {
+            self.patterns.push(pattern);
+            crate::listener::triggers_changed();
+        }
+    }
+    pub fn unsubscribe(&mut self, pattern: &str) {
+        // A path that was never interned cannot be one of the patterns
+        let Some(pattern) = Topic::lookup(pattern) else { return };
+        self.patterns.retain(|&p| p != pattern);
+        crate::listener::triggers_changed();
+    }
+}
+
+impl IListener<Topic, usize> for TopicListener {
+    fn get_triggers(&self) -> Vec<&Topic> {
+        self.patterns.iter().collect()
+    }
+    fn has_trigger(&self, tag: &Topic) -> bool {
+        self.patterns.iter().any(|&p| tag.matches(p))
+    }
+    fn on_triggers(&self, triggers: Vec<Event<Topic, usize>>) {
+        for e in &triggers {
+            (self.callback)(e);
+        }
+    }
+    fn as_lirc(&self) -> LiRC<Topic, usize> {
+        LiRC(Rc::new(RefCell::new(self.clone())))
+    }
+    fn into_lirc(self) -> Result<LiRC<Topic, usize>, &'static str> {
+        Ok(LiRC(Rc::new(RefCell::new(self))))
+    }
+    fn try_into_lirc(self) -> Option<LiRC<Topic, usize>> {
+        Some(LiRC(Rc::new(RefCell::new(self))))
+    }
+    fn as_emrc(&self) -> EmRC<usize> {
+        EmRC(Rc::new(RefCell::new(self.clone())))
+    }
+    fn into_emrc(self) -> EmRC<usize> {
+        EmRC(Rc::new(RefCell::new(self)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{event_handler::EventHandler as EH, def_emitter::DefEmitter as DEm};
+    use super::*;
+
+    fn matches(path: &str, pattern: &str) -> bool {
+        Topic::new(path).matches(Topic::new(pattern))
+    }
+
+    #[test]
+    fn interning() {
+        assert_eq!(Topic::new("ui.button.click"), Topic::from("ui.button.click"));
+        assert_eq!(Topic::new("ui.button.click").as_str(), "ui.button.click");
+        assert_eq!(Topic::new("ui.button.click").segments(), ["ui", "button", "click"]);
+        assert!(Topic::new("ui.*.click").is_pattern() && Topic::new("ui.#").is_pattern());
+        assert!(!Topic::new("ui.button").is_pattern());
+        assert_eq!(Topic::lookup("ui.button"), Some(Topic::new("ui.button")));
+        assert_eq!(Topic::lookup("never.interned"), None);
+    }
+
+    #[test]
+    fn wildcards() {
+        assert!(matches("ui.button.click", "ui.*.click"));
+        assert!(matches("ui.button.click", "ui.#"));
+        assert!(matches("ui", "ui.#"));
+        assert!(matches("ui.menu.file.open", "#.open"));
+        assert!(matches("ui.menu.file.open", "ui.#.file.#"));
+        assert!(matches("a.b.a.b.c", "#.a.b.c"));
+        assert!(!matches("ui.menu.file.click", "ui.*.click"));
+        assert!(!matches("net.button.click", "ui.#"));
+        assert!(!matches("ui", "ui.*"));
+        assert!(!matches("ui.menu", "#.file.#"));
+    }
+
+    #[test]
+    fn many_hashes_match_quickly() {
+        let path = vec!["a"; 40].join(".");
+        let pattern = format!("{}.b", vec!["#"; 40].join("."));
+        assert!(!matches(&path, &pattern));
+        assert!(matches(&format!("{}.b", path), &pattern));
+    }
+
+    #[test]
+    fn unsubscribe_does_not_intern() {
+        let mut li = TopicListener::new(&["ui.#"], |_| {});
+        li.unsubscribe("topic.unsubscribe.never.subscribed");
+        assert_eq!(Topic::lookup("topic.unsubscribe.never.subscribed"), None);
+        assert!(li.has_trigger(&Topic::new("ui.button")));
+
+        li.unsubscribe("ui.#");
+        assert!(!li.has_trigger(&Topic::new("ui.button")));
+    }
+
+    #[test]
+    fn listeners_get_matching_topics() {
+        let eh = EH::<Topic, usize>::new_ehrc();
+        let em = DEm::<Topic>::new_emrc(None);
+        let clicks = Rc::new(RefCell::new(vec![]));
+        let all_ui = Rc::new(RefCell::new(vec![]));
+        let (c, a) = (clicks.clone(), all_ui.clone());
+        eh.borrow_mut().add_listener(TopicListener::new_lirc(&["ui.*.click"], move |e| c.borrow_mut().push(e.get_tag().unwrap()))).unwrap();
+        eh.borrow_mut().add_listener(TopicListener::new_lirc(&["ui.#"], move |e| a.borrow_mut().push(e.get_tag().unwrap()))).unwrap();
+
+        eh.borrow_mut().publish(em.clone(), "ui.button.click");
+        eh.borrow_mut().publish(em.clone(), "ui.window.resize");
+        eh.borrow_mut().publish(em.clone(), "net.packet");
+        for _ in 0..3 {
+            eh.borrow_mut().consume_next_event();
+        }
+
+        assert_eq!(*clicks.borrow(), vec![Topic::new("ui.button.click")]);
+        assert_eq!(*all_ui.borrow(), vec![Topic::new("ui.window.resize"), Topic::new("ui.button.click")]);
+    }
+}
@@ -0,0 +1,345 @@
+use std::{mem::{self, Discriminant}, rc::Weak};
+use crate::{prelude::*, event::Event, event_handler::{EventHandler, ObserverHandle}, IDCOUNTER};
+
+type InverseFn<T, I> = Rc<dyn Fn(&Event<T, I>) -> Option<T>>;
+
+/// Consumed event along with the tag undoing it
+#[derive(Debug, Clone, PartialEq)]
+pub struct UndoAction<T: Tag, I: Id> {
+    pub emitter: EmRC<I>,
+    pub tag: T,
+    pub inverse: T,
+}
+
+struct UndoState<T: Tag, I: Id> {
+    /// Marks the events this manager replays, which are not recorded again
+    id: usize,
+    /// Exact tags with a fixed inverse
+    tags: Vec<(T, T)>,
+    /// Inverses computed for every tag of a variant
+    variants: Vec<(Discriminant<T>, InverseFn<T, I>)>,
+    undo: Vec<Vec<UndoAction<T, I>>>,
+    redo: Vec<Vec<UndoAction<T, I>>>,
+    /// Step being built while a group is open, and how many groups are open
+    group: Option<(Vec<UndoAction<T, I>>, usize)>,
+    limit: Option<usize>,
+}
+
+impl<T: Tag, I: Id> Debug for UndoState<T, I> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UndoState")
+            .field("id", &self.id)
+            .field("tags", &self.tags)
+            .field("variants", &self.variants.len())
+            .field("undo", &self.undo)
+            .field("redo", &self.redo)
+            .field("group", &self.group)
+            .field("limit", &self.limit)
+            .finish()
+    }
+}
+
+impl<T: Tag, I: Id> UndoState<T, I> {
+    fn inverse_of(&self, event: &Event<T, I>) -> Option<T> {
+        let tag = event.get_tag()?;
+        if let Some((_, inverse)) = self.tags.iter().find(|(t, _)| *t == tag) {
+            return Some(*inverse);
+        }
+        let (_, f) = self.variants.iter().find(|(d, _)| *d == mem::discriminant(&tag))?;
+        f(event)
+    }
+    fn record(&mut self, event: &Event<T, I>) {
+        if self.is_replay(event) || event.is_query() || event.is_reply() {
+            return;
+        }
+        let Some(inverse) = self.inverse_of(event) else { return };
+        let action = UndoAction { emitter: event.get_emitter(), tag: event.get_tag().unwrap(), inverse };
+
+        #[cfg(test)]
+        println!("UndoManager recorded {:?}", action);
+
+        self.redo.clear();
+        match &mut self.group {
+            Some((step, _)) => step.push(action),
+            None => self.push_step(vec![action]),
+        }
+    }
+    /// Whether this manager emitted `event`, or an event it was coalesced with, by undoing or redoing
+    fn is_replay(&self, event: &Event<T, I>) -> bool {
+        event.get_replayed_by() == Some(self.id)
+    }
+    fn replay(&self, emitter: EmRC<I>, tag: T) -> Event<T, I> {
+        let mut event = Event::new(emitter, Some(tag));
+        event.set_replayed_by(Some(self.id));
+        event
+    }
+    fn push_step(&mut self, step: Vec<UndoAction<T, I>>) {
+        if step.is_empty() {
+            return;
+        }
+        self.undo.push(step);
+        if let Some(limit) = self.limit && self.undo.len() > limit {
+            self.undo.remove(0);
+        }
+    }
+    fn close_groups(&mut self) {
+        if let Some((step, _)) = self.group.take() {
+            self.push_step(step);
+        }
+    }
+}
+
+/// Records the events consumed by an `EventHandler` so they can be undone and redone.
+///
+/// Only events with a registered inverse are recorded. Undoing pushes the inverse
+/// events back into the handler and redoing pushes the original events again,
+/// to be applied as they get consumed. Replayed events are marked with the
+/// manager's id so they are not recorded again, even once coalesced. Recording
+/// stops once the manager is dropped.
+#[derive(Debug)]
+pub struct UndoManager<T: Tag, I: Id> {
+    inbox: Inbox<T, I>,
+    state: Rc<RefCell<UndoState<T, I>>>,
+    _observer: ObserverHandle<T, I>,
+}
+
+impl<T: Tag, I: Id> UndoManager<T, I> {
+    pub fn new(eh: &EHRc<T, I>) -> Self {
+        let state = Rc::new(RefCell::new(UndoState {
+            id: IDCOUNTER.fetch_add(1, std::sync::atomic::Ordering::SeqCst),
+            tags: Vec::new(),
+            variants: Vec::new(),
+            undo: Vec::new(),
+            redo: Vec::new(),
+            group: None,
+            limit: None,
+        }));
+        let weak: Weak<RefCell<UndoState<T, I>>> = Rc::downgrade(&state);
+        let observer = EventHandler::observe(eh, move |e| {
+            if let Some(state) = weak.upgrade() {
+                state.borrow_mut().record(e);
+            }
+        });
+        Self { inbox: Inbox::new(eh), state, _observer: observer }
+    }
+    /// Undoes `tag` by emitting `inverse`
+    pub fn set_inverse(&mut self, tag: T, inverse: T) {
+        let mut state = self.state.borrow_mut();
+        state.tags.retain(|(t, _)| *t != tag);
+        state.tags.push((tag, inverse));
+    }
+    /// Undoes every tag of the same variant as `tag` with the tag `f` computes,
+    /// unless an exact inverse is set. Events `f` returns `None` for are not recorded.
+    pub fn set_inverse_with(&mut self, tag: T, f: impl Fn(&Event<T, I>) -> Option<T> + 'static) {
+        let mut state = self.state.borrow_mut();
+        state.variants.retain(|(d, _)| *d != mem::discriminant(&tag));
+        state.variants.push((mem::discriminant(&tag), Rc::new(f)));
+    }
+    /// Events consumed until the matching `end_group` are undone as one step.
+    /// Groups can be nested, only the outermost one makes a step.
+    pub fn begin_group(&mut self) {
+        let mut state = self.state.borrow_mut();
+        match &mut state.group {
+            Some((_, depth)) => *depth += 1,
+            None => state.group = Some((vec![], 1)),
+        }
+    }
+    pub fn end_group(&mut self) {
+        let mut state = self.state.borrow_mut();
+        if let Some((_, depth)) = &mut state.group {
+            *depth -= 1;
+            if *depth == 0 {
+                state.close_groups();
+            }
+        }
+    }
+    /// Caps how many steps can be undone, the oldest are forgotten first
+    pub fn set_limit(&mut self, limit: Option<usize>) {
+        let mut state = self.state.borrow_mut();
+        state.limit = limit;
+        if let Some(limit) = limit {
+            let excess = state.undo.len().saturating_sub(limit);
+            state.undo.drain(..excess);
+        }
+    }
+    pub fn can_undo(&self) -> bool {
+        let state = self.state.borrow();
+        !state.undo.is_empty() || state.group.as_ref().is_some_and(|(step, _)| !step.is_empty())
+    }
+    pub fn can_redo(&self) -> bool {
+        !self.state.borrow().redo.is_empty()
+    }
+    pub fn get_undo_steps(&self) -> Vec<Vec<UndoAction<T, I>>> {
+        self.state.borrow().undo.clone()
+    }
+    pub fn get_redo_steps(&self) -> Vec<Vec<UndoAction<T, I>>> {
+        self.state.borrow().redo.clone()
+    }
+    /// Pushes the inverses of the last step into the handler, last action first.
+    /// Open groups are closed first. Returns false if there was nothing to undo.
+    pub fn undo(&mut self) -> bool {
+        let events = {
+            let mut state = self.state.borrow_mut();
+            state.close_groups();
+            let Some(step) = state.undo.pop() else { return false };
+            // Pushed first is consumed last
+            let events: Vec<Event<T, I>> = step.iter().map(|a| state.replay(a.emitter.clone(), a.inverse)).collect();
+            state.redo.push(step);
+            events
+        };
+
+        #[cfg(test)]
+        println!("UndoManager undid {:?}", events);
+
+        for e in events {
+            self.inbox.push(e);
+        }
+        true
+    }
+    /// Pushes the events of the last undone step into the handler again, in their original order.
+    /// Returns false if there was nothing to redo.
+    pub fn redo(&mut self) -> bool {
+        let events = {
+            let mut state = self.state.borrow_mut();
+            let Some(step) = state.redo.pop() else { return false };
+            let events: Vec<Event<T, I>> = step.iter().rev().map(|a| state.replay(a.emitter.clone(), a.tag)).collect();
+            state.push_step(step);
+            events
+        };
+
+        #[cfg(test)]
+        println!("UndoManager redid {:?}", events);
+
+        for e in events {
+            self.inbox.push(e);
+        }
+        true
+    }
+    /// Forgets every step
+    pub fn clear(&mut self) {
+        let mut state = self.state.borrow_mut();
+        state.undo.clear();
+        state.redo.clear();
+        state.group = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        event_handler::CoalescePolicy,
+        def_emitter::DefEmitter as DEm,
+        tests::{TestTags::{self, *}, Recorder},
+    };
+    use super::*;
+
+    /// Handler recording what it consumes, with T1 undone by T2 and T4(n) by T4(-n)
+    fn history() -> (EHRc<TestTags, usize>, Recorder, UndoManager<TestTags, usize>) {
+        let eh = EventHandler::<TestTags, usize>::new_ehrc();
+        let rec = Recorder::new(vec![T1, T2, T3, T4(5), T4(-5), T4(2), T4(-2), T4(3)], None);
+        eh.borrow_mut().add_listener(rec.as_lirc()).unwrap();
+        let mut history = UndoManager::new(&eh);
+        history.set_inverse(T1, T2);
+        history.set_inverse_with(T4(0), |e| match e.get_tag() {
+            Some(T4(n)) => Some(T4(-n)),
+            _ => None,
+        });
+        (eh, rec, history)
+    }
+
+    fn run(eh: &EHRc<TestTags, usize>, tags: &[TestTags]) {
+        let em = DEm::<TestTags>::new_emrc(None);
+        for tag in tags {
+            eh.borrow_mut().emit(em.clone(), *tag);
+        }
+        eh.borrow_mut().run_until_empty(Default::default());
+    }
+
+    #[test]
+    fn groups_make_one_step() {
+        let (eh, _, mut history) = history();
+        run(&eh, &[T4(5)]);
+        history.begin_group();
+        history.begin_group();
+        run(&eh, &[T1, T3]);
+        history.end_group();
+        run(&eh, &[T4(2)]);
+        history.end_group();
+
+        let steps = history.get_undo_steps();
+        assert_eq!(steps.iter().map(|s| s.len()).collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(steps[1].iter().map(|a| a.inverse).collect::<Vec<_>>(), vec![T2, T4(-2)]);
+    }
+
+    #[test]
+    fn undo_and_redo_steps() {
+        let (eh, rec, mut history) = history();
+        run(&eh, &[T4(5)]);
+        history.begin_group();
+        run(&eh, &[T1]);
+        run(&eh, &[T4(2)]);
+        history.end_group();
+
+        rec.received.borrow_mut().clear();
+        assert!(history.undo());
+        run(&eh, &[]);
+        assert_eq!(rec.received_tags(), vec![Some(T4(-2)), Some(T2)]);
+        assert!(history.undo());
+        assert!(!history.undo());
+        run(&eh, &[]);
+        assert!(!history.can_undo());
+
+        rec.received.borrow_mut().clear();
+        assert!(history.redo());
+        run(&eh, &[]);
+        assert!(history.redo());
+        run(&eh, &[]);
+        assert_eq!(rec.received_tags(), vec![Some(T4(5)), Some(T1), Some(T4(2))]);
+        assert!(!history.can_redo());
+        assert_eq!(history.get_undo_steps().len(), 2);
+
+        // New actions make undone steps unreachable
+        history.undo();
+        run(&eh, &[T1]);
+        assert!(!history.can_redo());
+        assert_eq!(history.get_undo_steps().last().unwrap()[0].inverse, T2);
+    }
+
+    #[test]
+    fn limit_forgets_the_oldest_steps() {
+        let (eh, _, mut history) = history();
+        run(&eh, &[T1]);
+        run(&eh, &[T4(2)]);
+        run(&eh, &[T4(5)]);
+        history.set_limit(Some(2));
+        assert_eq!(history.get_undo_steps().iter().map(|s| s[0].tag).collect::<Vec<_>>(), vec![T4(2), T4(5)]);
+        run(&eh, &[T1]);
+        assert_eq!(history.get_undo_steps().iter().map(|s| s[0].tag).collect::<Vec<_>>(), vec![T4(5), T1]);
+    }
+
+    #[test]
+    fn replays_merged_into_pending_events_are_not_recorded() {
+        let (eh, _, mut history) = history();
+        eh.borrow_mut().set_coalesce_policy(CoalescePolicy::Merge(Rc::new(|p, new| match (p.get_tag(), new.get_tag()) {
+            (Some(T4(a)), Some(T4(b))) => Some(Event::new(new.get_emitter(), Some(T4(a + b)))),
+            _ => None,
+        })));
+        run(&eh, &[T4(2)]);
+
+        let em = DEm::<TestTags>::new_emrc(None);
+        eh.borrow_mut().emit(em.clone(), T4(5));
+        assert!(history.undo());
+        run(&eh, &[]);
+        assert_eq!(eh.borrow().get_prev_event().unwrap().get_tag(), Some(T4(3)));
+        assert!(history.get_undo_steps().is_empty());
+        assert!(history.can_redo());
+    }
+
+    #[test]
+    fn dropping_the_manager_stops_recording() {
+        let (eh, _, history) = history();
+        assert_eq!(eh.borrow().get_observer_count(), 1);
+        drop(history);
+        assert_eq!(eh.borrow().get_observer_count(), 0);
+    }
+}
@@ -0,0 +1,519 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+use crate::{prelude::*, event::Event, event_handler::EventHandler};
+use crate::codec::{self, Metadata, TagCodec};
+
+/// Records are a little-endian `u32` body length, the `u32` CRC-32 of the body, then the body.
+/// Bodies start with one of the operations below.
+const HEADER_LEN: usize = 8;
+const OP_PUSH: u8 = 0;
+const OP_PUSH_FRONT: u8 = 1;
+const OP_REPLACE: u8 = 2;
+const OP_REMOVE: u8 = 3;
+const OP_CLEAR: u8 = 4;
+
+/// Change made to a handler's stack, as seen by its journal
+pub(crate) enum StackOp<'a, T: Tag, I: Id> {
+    Push(&'a Event<T, I>),
+    PushFront(&'a Event<T, I>),
+    /// Pending event with the given id replaced in place, by coalescing
+    Replace(usize, &'a Event<T, I>),
+    Remove(usize),
+    Clear,
+}
+
+/// Keeps a handler's pending events somewhere they outlive the process
+pub(crate) trait Journal<T: Tag, I: Id> {
+    fn record(&mut self, op: StackOp<T, I>);
+    fn compact(&mut self) -> io::Result<()>;
+    fn get_stats(&self) -> WalStats;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WalOptions {
+    /// Flush every record to disk before going on
+    pub sync: bool,
+    /// Compact the log once this many events were taken off the stack, 0 never compacts on its own
+    pub compact_after: usize,
+}
+
+impl Default for WalOptions {
+    fn default() -> Self {
+        Self { sync: true, compact_after: 1024 }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct WalStats {
+    /// Events persisted and not consumed yet
+    pub pending: usize,
+    pub compactions: usize,
+    /// Records that could not be written, their events may be lost or replayed
+    pub write_errors: usize,
+}
+
+/// What `open_wal` found in an existing log
+#[derive(Debug, Clone, PartialEq)]
+pub struct WalRecovery<T: Tag> {
+    /// Events pushed back onto the stack, those coalesced into pending events or
+    /// dropped by the overflow policy included, but not the rejected ones
+    pub restored: usize,
+    /// Restored events whose emitter is not registered with the handler,
+    /// they come from a `StoredEmitter` with the same id
+    pub unresolved: Vec<(usize, Option<T>)>,
+    /// Restored events the handler's full stack rejected, they are left out of the log
+    pub rejected: Vec<(usize, Option<T>)>,
+    /// Bytes at the end of the log that were torn or failed their checksum
+    pub discarded_bytes: usize,
+}
+
+/// Stands in for the emitter of a restored event that is not registered with the handler
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoredEmitter {
+    id: usize,
+}
+
+impl EmitObj<usize> for StoredEmitter {
+    fn get_id(&self) -> usize {
+        self.id
+    }
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in bytes {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+fn frame(body: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(HEADER_LEN + body.len());
+    frame.extend((body.len() as u32).to_le_bytes());
+    frame.extend(crc32(body).to_le_bytes());
+    frame.extend(body);
+    frame
+}
+
+fn read_u64(bytes: &[u8]) -> Option<(u64, &[u8])> {
+    let (n, rest) = bytes.split_at_checked(8)?;
+    Some((u64::from_le_bytes(n.try_into().unwrap()), rest))
+}
+
+/// Persisted pending events in stack order, as (event id, payload) pairs.
+/// Payloads are the `u64` emitter id, the event's timestamp and headers as written by
+/// `codec::encode_metadata`, a byte telling whether there is a tag, then the encoded tag.
+type Live = Vec<(u64, Vec<u8>)>;
+
+/// Replays the records of `bytes` onto an empty stack,
+/// stopping at the first torn or corrupted record
+fn replay(bytes: &[u8]) -> (Live, usize) {
+    let mut live: Live = vec![];
+    let mut rest = bytes;
+    while rest.len() >= HEADER_LEN {
+        let len = u32::from_le_bytes(rest[..4].try_into().unwrap()) as usize;
+        let crc = u32::from_le_bytes(rest[4..8].try_into().unwrap());
+        let Some(body) = rest[HEADER_LEN..].get(..len) else { break };
+        if crc32(body) != crc || !apply(&mut live, body) {
+            break;
+        }
+        rest = &rest[HEADER_LEN + len..];
+    }
+    (live, rest.len())
+}
+
+/// Returns false if `body` is not a valid record
+fn apply(live: &mut Live, body: &[u8]) -> bool {
+    let Some((&op, args)) = body.split_first() else { return false };
+    match op {
+        OP_PUSH | OP_PUSH_FRONT => {
+            let Some((id, payload)) = read_u64(args) else { return false };
+            let pos = if op == OP_PUSH { live.len() } else { 0 };
+            live.insert(pos, (id, payload.to_vec()));
+        }
+        OP_REPLACE => {
+            let Some((old, args)) = read_u64(args) else { return false };
+            let Some((id, payload)) = read_u64(args) else { return false };
+            match live.iter().position(|(i, _)| *i == old) {
+                Some(pos) => live[pos] = (id, payload.to_vec()),
+                None => live.push((id, payload.to_vec())),
+            }
+        }
+        OP_REMOVE => {
+            let Some((id, _)) = read_u64(args) else { return false };
+            // Clones of an event share its id, each removal only takes one of them
+            if let Some(pos) = live.iter().position(|(i, _)| *i == id) {
+                live.remove(pos);
+            }
+        }
+        OP_CLEAR => live.clear(),
+        _ => return false,
+    }
+    true
+}
+
+/// Checksummed write-ahead log of a handler's stack.
+///
+/// Every change to the stack is appended to the log, and events are only
+/// marked done once they have been broadcast, so a crash while dispatching
+/// replays them on the next start: processing is at-least-once.
+/// Queries and replies are not persisted.
+pub(crate) struct WriteAheadLog<T: Tag> {
+    path: PathBuf,
+    file: File,
+    codec: Rc<dyn TagCodec<T>>,
+    options: WalOptions,
+    live: Live,
+    taken_since_compaction: usize,
+    stats: WalStats,
+}
+
+impl<T: Tag> Debug for WriteAheadLog<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WriteAheadLog")
+            .field("path", &self.path)
+            .field("options", &self.options)
+            .field("stats", &self.get_stats())
+            .finish()
+    }
+}
+
+impl<T: Tag> WriteAheadLog<T> {
+    fn payload(&self, event: &Event<T, usize>) -> Vec<u8> {
+        let mut payload = (event.get_emitter().borrow().get_id() as u64).to_le_bytes().to_vec();
+        codec::encode_metadata(event, &mut payload);
+        match event.get_tag() {
+            Some(tag) => {
+                payload.push(1);
+                self.codec.encode(&tag, &mut payload);
+            }
+            None => payload.push(0),
+        }
+        payload
+    }
+    fn decode(&self, payload: &[u8]) -> Option<(usize, Option<T>, Metadata)> {
+        let (emitter, rest) = read_u64(payload)?;
+        let (metadata, rest) = codec::decode_metadata(rest)?;
+        let tag = match rest.split_first()? {
+            (0, _) => None,
+            (1, tag) => Some(self.codec.decode(tag)?),
+            _ => return None,
+        };
+        Some((emitter as usize, tag, metadata))
+    }
+    /// Appends the record to the log and applies it to the pending events
+    fn log(&mut self, body: Vec<u8>) {
+        let written = self.file.write_all(&frame(&body))
+            .and_then(|_| if self.options.sync { self.file.sync_data() } else { Ok(()) });
+        if let Err(_e) = written {
+            #[cfg(test)]
+            println!("WriteAheadLog at {:?} failed to write: {}", self.path, _e);
+
+            self.stats.write_errors += 1;
+        }
+        apply(&mut self.live, &body);
+    }
+    fn taken(&mut self, n: usize) {
+        self.taken_since_compaction += n;
+        if self.options.compact_after > 0 && self.taken_since_compaction >= self.options.compact_after && self.compact().is_err() {
+            self.stats.write_errors += 1;
+        }
+    }
+}
+
+impl<T: Tag> Journal<T, usize> for WriteAheadLog<T> {
+    fn record(&mut self, op: StackOp<T, usize>) {
+        let persisted = |e: &Event<T, usize>| !e.is_query() && !e.is_reply();
+        match op {
+            StackOp::Push(e) | StackOp::PushFront(e) if persisted(e) => {
+                let mut body = vec![if matches!(op, StackOp::Push(_)) { OP_PUSH } else { OP_PUSH_FRONT }];
+                body.extend((e.get_id() as u64).to_le_bytes());
+                body.extend(self.payload(e));
+                self.log(body);
+            }
+            StackOp::Replace(old, e) if persisted(e) => {
+                let mut body = vec![OP_REPLACE];
+                body.extend((old as u64).to_le_bytes());
+                body.extend((e.get_id() as u64).to_le_bytes());
+                body.extend(self.payload(e));
+                self.log(body);
+            }
+            StackOp::Remove(id) if self.live.iter().any(|(i, _)| *i == id as u64) => {
+                let mut body = vec![OP_REMOVE];
+                body.extend((id as u64).to_le_bytes());
+                self.log(body);
+                self.taken(1);
+            }
+            StackOp::Clear => {
+                let n = self.live.len();
+                self.log(vec![OP_CLEAR]);
+                self.taken(n);
+            }
+            _ => {}
+        }
+    }
+    /// Rewrites the log with only the pending events
+    fn compact(&mut self) -> io::Result<()> {
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".compact");
+        let tmp = PathBuf::from(tmp);
+
+        let mut file = File::create(&tmp)?;
+        for (id, payload) in &self.live {
+            file.write_all(&frame(&[&[OP_PUSH], &id.to_le_bytes()[..], payload].concat()))?;
+        }
+        file.sync_all()?;
+        fs::rename(&tmp, &self.path)?;
+        self.file = OpenOptions::new().append(true).open(&self.path)?;
+
+        #[cfg(test)]
+        println!("WriteAheadLog at {:?} compacted down to {} events", self.path, self.live.len());
+
+        self.taken_since_compaction = 0;
+        self.stats.compactions += 1;
+        Ok(())
+    }
+    fn get_stats(&self) -> WalStats {
+        WalStats { pending: self.live.len(), ..self.stats }
+    }
+}
+
+impl<T: Tag> EventHandler<T, usize> {
+    /// Persists the stack to a write-ahead log at `path`, first restoring the events
+    /// pending in an existing log underneath the ones already on the stack.
+    /// Restored events keep their timestamp and headers, but get new ids and sequence numbers,
+    /// resolve their emitters through the registry, and are pushed like with `try_push_event_front`,
+    /// coalescing and overflow included.
+    /// If the log cannot be rewritten the handler is left without one,
+    /// with the restored events on its stack.
+    pub fn open_wal(&mut self, path: impl AsRef<Path>, codec: impl TagCodec<T> + 'static, options: WalOptions) -> io::Result<WalRecovery<T>> {
+        self.close_wal();
+        let path = path.as_ref().to_path_buf();
+        let mut bytes = vec![];
+        match File::open(&path) {
+            Ok(mut file) => {
+                file.read_to_end(&mut bytes)?;
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        let (stored, discarded_bytes) = replay(&bytes);
+
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let mut wal = WriteAheadLog { path, file, codec: Rc::new(codec), options, live: vec![], taken_since_compaction: 0, stats: WalStats::default() };
+
+        let mut restored = vec![];
+        let mut unresolved = vec![];
+        for (_, payload) in stored {
+            // Undecodable events are dropped, the log is rewritten without them below
+            let Some((emitter_id, tag, metadata)) = wal.decode(&payload) else { continue };
+            let emitter = self.get_emitter_by_id(&emitter_id).unwrap_or_else(|| {
+                unresolved.push((emitter_id, tag));
+                EmRC(Rc::new(RefCell::new(StoredEmitter { id: emitter_id })))
+            });
+            restored.push(metadata.apply(Event::new(emitter, tag)));
+        }
+        let mut recovery = WalRecovery { restored: restored.len(), unresolved, rejected: vec![], discarded_bytes };
+
+        for e in self.get_stack() {
+            wal.record(StackOp::Push(e));
+        }
+        let wal = Rc::new(RefCell::new(wal));
+        self.set_journal(Some(wal.clone()));
+        for e in restored.into_iter().rev() {
+            if let Err(full) = self.try_push_event_front(e) {
+                recovery.restored -= 1;
+                recovery.rejected.push((full.event.get_emitter().borrow().get_id(), full.event.get_tag()));
+            }
+        }
+        // The restored events are now in the log twice, under their old and new ids
+        if let Err(e) = wal.borrow_mut().compact() {
+            self.close_wal();
+            return Err(e);
+        }
+
+        #[cfg(test)]
+        println!("{} opened a write-ahead log: {:?}", self, recovery);
+
+        Ok(recovery)
+    }
+}
+
+impl<T: Tag, I: Id> EventHandler<T, I> {
+    pub fn has_wal(&self) -> bool {
+        self.get_journal().is_some()
+    }
+    /// Stops persisting the stack, the log is left on disk as it is
+    pub fn close_wal(&mut self) -> bool {
+        let had = self.has_wal();
+        self.set_journal(None);
+        had
+    }
+    /// Rewrites the log with only the pending events
+    pub fn compact_wal(&mut self) -> io::Result<()> {
+        match self.get_journal() {
+            Some(journal) => journal.borrow_mut().compact(),
+            None => Ok(()),
+        }
+    }
+    pub fn get_wal_stats(&self) -> Option<WalStats> {
+        self.get_journal().map(|journal| journal.borrow().get_stats())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Write, panic::{self, AssertUnwindSafe}};
+    use crate::{
+        def_emitter::DefEmitter as DEm,
+        event_handler::CoalescePolicy,
+        tests::{TestTags::{self, *}, TestCodec},
+    };
+    use super::*;
+
+    const OPTIONS: WalOptions = WalOptions { sync: false, compact_after: 0 };
+
+    fn wal_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("event_handler_{}_{}.wal", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn recover_after_a_crash() {
+        let path = wal_path("crash");
+        let em = DEm::<TestTags>::new_emrc(None);
+        let stranger = DEm::<TestTags>::new_emrc(None);
+
+        let mut eh = EventHandler::<TestTags, usize>::new();
+        eh.emit(em.clone(), T1);
+        let recovery = eh.open_wal(&path, TestCodec, OPTIONS).unwrap();
+        assert_eq!((recovery.restored, recovery.discarded_bytes), (0, 0));
+        assert!(!eh.clone().has_wal());
+        let stored = Event::new(stranger.clone(), Some(T3)).with_header("trace", "7");
+        eh.push_event(Some(stored.clone()));
+        eh.emit(em.clone(), T4(3));
+        eh.emit(em.clone(), T2);
+        eh.consume_next_event();
+        assert_eq!(eh.get_wal_stats().unwrap().pending, 3);
+
+        // Crashing while dispatching leaves the event in the log
+        eh.add_observer(|e| if e.get_tag() == Some(T4(3)) { panic!("crashed while dispatching") });
+        assert!(panic::catch_unwind(AssertUnwindSafe(|| eh.consume_next_event())).is_err());
+        drop(eh);
+        OpenOptions::new().append(true).open(&path).unwrap().write_all(&[7, 0, 0]).unwrap();
+
+        let mut eh = EventHandler::<TestTags, usize>::new();
+        eh.register_emitter(em.clone()).unwrap();
+        let recovery = eh.open_wal(&path, TestCodec, OPTIONS).unwrap();
+        assert_eq!(recovery.restored, 3);
+        assert_eq!(recovery.discarded_bytes, 3);
+        assert_eq!(recovery.unresolved, vec![(stranger.borrow().get_id(), Some(T3))]);
+        assert_eq!(eh.get_stack_tags(), vec![Some(T1), Some(T3), Some(T4(3))]);
+        assert_eq!(eh.get_stack_emitters(), vec![em.clone(), stranger.clone(), em.clone()]);
+        // Timestamps and headers are restored, sequence numbers are new
+        let restored = &eh.get_stack()[1];
+        assert_eq!((restored.get_timestamp(), restored.get_header("trace")), (stored.get_timestamp(), Some("7")));
+        assert!(restored.get_sequence() > stored.get_sequence());
+
+        eh.run_until_empty(Default::default());
+        assert_eq!(eh.get_wal_stats().unwrap().pending, 0);
+        eh.compact_wal().unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), 0);
+        assert!(eh.close_wal());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn restored_events_are_pushed_under_pending_ones() {
+        let path = wal_path("restore");
+        let em = DEm::<TestTags>::new_emrc(None);
+
+        let mut eh = EventHandler::<TestTags, usize>::new();
+        eh.open_wal(&path, TestCodec, OPTIONS).unwrap();
+        eh.emit(em.clone(), T1);
+        eh.emit(em.clone(), T3);
+        drop(eh);
+
+        // T1 is already pending, so the restored one is coalesced into it
+        let mut eh = EventHandler::<TestTags, usize>::new();
+        eh.register_emitter(em.clone()).unwrap();
+        eh.set_coalesce_policy(CoalescePolicy::DropDuplicates);
+        eh.emit(em.clone(), T2);
+        eh.emit(em.clone(), T1);
+        let recovery = eh.open_wal(&path, TestCodec, OPTIONS).unwrap();
+        assert_eq!(recovery.restored, 2);
+        assert_eq!(eh.get_coalesced_count(), 1);
+        assert_eq!(eh.get_stack_tags(), vec![Some(T3), Some(T2), Some(T1)]);
+        assert_eq!(eh.get_wal_stats().unwrap().pending, 3);
+        drop(eh);
+
+        let mut eh = EventHandler::<TestTags, usize>::new();
+        eh.register_emitter(em.clone()).unwrap();
+        assert_eq!(eh.open_wal(&path, TestCodec, OPTIONS).unwrap().restored, 3);
+        assert_eq!(eh.get_stack_tags(), vec![Some(T3), Some(T2), Some(T1)]);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn full_stacks_reject_restored_events() {
+        let path = wal_path("full");
+        let em = DEm::<TestTags>::new_emrc(None);
+        let id = em.borrow().get_id();
+
+        let mut eh = EventHandler::<TestTags, usize>::new();
+        eh.open_wal(&path, TestCodec, OPTIONS).unwrap();
+        for tag in [T1, T2, T3] {
+            eh.emit(em.clone(), tag);
+        }
+        drop(eh);
+
+        // Restored events go under the pending one until the stack is full
+        let mut eh = EventHandler::<TestTags, usize>::new();
+        eh.register_emitter(em.clone()).unwrap();
+        eh.set_capacity(Some(2));
+        eh.emit(em.clone(), T4(0));
+        let recovery = eh.open_wal(&path, TestCodec, OPTIONS).unwrap();
+        assert_eq!(recovery.restored, 1);
+        assert_eq!(recovery.rejected, vec![(id, Some(T2)), (id, Some(T1))]);
+        assert_eq!(eh.get_stack_tags(), vec![Some(T3), Some(T4(0))]);
+        assert_eq!(eh.get_dead_letter_count(), 0);
+        drop(eh);
+
+        // Rejected events are left out of the log
+        let mut eh = EventHandler::<TestTags, usize>::new();
+        eh.register_emitter(em.clone()).unwrap();
+        assert_eq!(eh.open_wal(&path, TestCodec, OPTIONS).unwrap().restored, 2);
+        assert_eq!(eh.get_stack_tags(), vec![Some(T3), Some(T4(0))]);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn removing_one_of_several_clones() {
+        let path = wal_path("clones");
+        let em = DEm::<TestTags>::new_emrc(None);
+
+        let mut eh = EventHandler::<TestTags, usize>::new();
+        eh.open_wal(&path, TestCodec, OPTIONS).unwrap();
+        let event = Event::new(em.clone(), Some(T1));
+        eh.push_event(Some(event.clone()));
+        eh.push_event(Some(event));
+        eh.consume_next_event();
+        assert_eq!(eh.get_wal_stats().unwrap().pending, 1);
+        drop(eh);
+
+        let mut eh = EventHandler::<TestTags, usize>::new();
+        assert_eq!(eh.open_wal(&path, TestCodec, OPTIONS).unwrap().restored, 1);
+
+        fs::remove_file(&path).unwrap();
+    }
+}